@@ -5,6 +5,8 @@
 ---@field log_level integer Minimum log level for notifications (vim.log.levels)
 ---@field token_log boolean Whether to enable token logging to file for integration testing
 ---@field recording_on boolean Whether recording is on by default when the plugin starts
+---@field hardtime boolean Whether repeated hjkl presses beyond hardtime_max_repeats earn no XP and are recorded as penalties
+---@field hardtime_max_repeats integer How many identical hjkl presses in a row are allowed before hardtime flags them
 ---@field key_overrides table<string, string> Map of physical keys to substituted keys for the lexer (e.g. { [";"] = ":" })
 local M = {
   db_path = vim.fn.stdpath("data") .. "/vimscape2007/",
@@ -12,6 +14,8 @@ local M = {
   batch_size = 1000,
   log_level = vim.log.levels.INFO,
   token_log = false,
+  hardtime = false,
+  hardtime_max_repeats = 3,
   key_overrides = {},
   recording_on = true,
 }
@@ -3,12 +3,19 @@
 ---@field set_active function Set active status
 ---@field get_typed_letters function Get typed letters
 ---@field set_typed_letters function Set typed letters
----@field clear_typed_letters function Clear typed letters
+---@field clear_typed_letters function Clear typed letters with their modes and times
+---@field get_typed_modes function Get the mode each typed letter was typed in
+---@field get_typed_times function Get the time in ms each typed letter was typed at
+---@field get_batch_recording function Get whether a macro was recording when the batch started
+---@field set_batch_recording function Set whether a macro was recording when the batch started
 local M = {}
 
 local state = {
 	active = false,
 	typed_letters = {},
+	typed_modes = {},
+	typed_times = {},
+	batch_recording = false,
 }
 
 M.get_active = function()
@@ -29,6 +36,24 @@ end
 
 M.clear_typed_letters = function()
 	state.typed_letters = {}
+	state.typed_modes = {}
+	state.typed_times = {}
+end
+
+M.get_typed_modes = function()
+	return state.typed_modes
+end
+
+M.get_typed_times = function()
+	return state.typed_times
+end
+
+M.get_batch_recording = function()
+	return state.batch_recording
+end
+
+M.set_batch_recording = function(val)
+	state.batch_recording = val
 end
 
 return M
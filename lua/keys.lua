@@ -11,8 +11,10 @@ local M = {}
 
 ---@return string | nil
 M.sanitize_key = function(key)
+	-- `|` and `<` go through keytrans as <Bar> and <lt>, sent raw they would
+	-- read as the start of a |key| or <key> name
 	local b = key:byte()
-	if b and b >= 33 and b <= 126 then
+	if b and b >= 33 and b <= 126 and key ~= "|" and key ~= "<" then
 		return key
 	end
 
@@ -21,7 +23,7 @@ M.sanitize_key = function(key)
 	local skip_patterns = {
 		"^<t_..>$",  -- Terminal codes
 		"<Cmd>",    -- Command key
-		"<LeftMouse>", "<RightMouse>", "<MiddleMouse>", "<Mouse>", "<ScrollWheel>", "<LeftDrag>", "<LeftRelease>", "<RightDrag>", "<RightRelease>", "<MiddleDrag>", "<MiddleRelease>", "<2-LeftMouse>", "<2-RightMouse>", "<2-MiddleMouse>", "<3-LeftMouse>", "<3-RightMouse>", "<3-MiddleMouse>", "<4-LeftMouse>", "<4-RightMouse>", "<4-MiddleMouse>",
+		"<Mouse>", "<ScrollWheel>", "<LeftDrag>", "<LeftRelease>", "<RightDrag>", "<RightRelease>", "<MiddleDrag>", "<MiddleRelease>",
 		"<F13>", "<F14>", "<F15>", "<F16>", "<F17>", "<F18>", "<F19>", "<F20>", "<F21>", "<F22>", "<F23>", "<F24>", "<F25>", "<F26>", "<F27>", "<F28>", "<F29>", "<F30>", "<F31>", "<F32>", "<F33>", "<F34>", "<F35>", "<F36>", "<F37>",
 	}
 
@@ -47,6 +49,19 @@ M.sanitize_key = function(key)
 		["<PageUp>"] = "|pageup|",
 		["<PageDown>"] = "|pagedown|",
 		["<Insert>"] = "|insert|",
+		-- Clicks are tracked as an anti-pattern, drags and scrolls are skipped above
+		["<LeftMouse>"] = "|mouse|",
+		["<RightMouse>"] = "|mouse|",
+		["<MiddleMouse>"] = "|mouse|",
+		["<2-LeftMouse>"] = "|mouse|",
+		["<2-RightMouse>"] = "|mouse|",
+		["<2-MiddleMouse>"] = "|mouse|",
+		["<3-LeftMouse>"] = "|mouse|",
+		["<3-RightMouse>"] = "|mouse|",
+		["<3-MiddleMouse>"] = "|mouse|",
+		["<4-LeftMouse>"] = "|mouse|",
+		["<4-RightMouse>"] = "|mouse|",
+		["<4-MiddleMouse>"] = "|mouse|",
 	}
 
 	if translate_map[translated] then
@@ -56,6 +71,37 @@ M.sanitize_key = function(key)
 	return translated
 end
 
+---Frame each typed key as `{mode}:{bytes}:{ms};` for the backend, with the
+---mode it was typed in and the monotonic time it was typed at
+---@param typed_letters string[]
+---@param typed_modes string[]
+---@param typed_times integer[]
+---@return string
+M.encode_frames = function(typed_letters, typed_modes, typed_times)
+	local frames = {}
+	for i, key in ipairs(typed_letters) do
+		table.insert(frames, (typed_modes[i] or "") .. ":" .. #key .. ":" .. typed_times[i] .. ";")
+	end
+	return table.concat(frames)
+end
+
+---Send the current leader keys to the backend, which strips their echoes.
+---They are read for every batch since they can be set after setup.
+M.sync_leaders = function()
+	vimscape.set_leaders(vim.g.mapleader or "\\", vim.g.maplocalleader or "\\")
+end
+
+---Tell the backend whether a macro was recording when the batch's first key
+---was typed, so a `q` it carries over is not guessed to stop a recording
+---@param db_path string
+M.sync_recording = function(db_path)
+	local recording = globals.get_batch_recording()
+	if #globals.get_typed_letters() == 0 then
+		recording = vim.fn.reg_recording() ~= ""
+	end
+	vimscape.set_recording(recording, db_path)
+end
+
 M.record_keys = function(typed, db_path, batch_size, config)
 	if not globals.get_active() then
 		return
@@ -70,9 +116,6 @@ M.record_keys = function(typed, db_path, batch_size, config)
 	end
 
 	local mode = vim.api.nvim_get_mode().mode
-	if mode == "i" then
-		return
-	end
 
 	local new_key = M.sanitize_key(typed)
 
@@ -84,15 +127,32 @@ M.record_keys = function(typed, db_path, batch_size, config)
 		new_key = config.key_overrides[new_key]
 	end
 
+	-- Insert mode text is not lexed, the key is kept without bytes so its
+	-- time still counts as active time
+	if mode == "i" then
+		new_key = ""
+	end
+
 	local typed_letters = globals.get_typed_letters()
 	if #typed_letters >= batch_size and vimscape then
 		local string_value = table.concat(typed_letters)
-		vimscape.process_batch(string_value, db_path)
+		local frames = M.encode_frames(typed_letters, globals.get_typed_modes(), globals.get_typed_times())
+		M.sync_leaders()
+		M.sync_recording(db_path)
+		vimscape.process_mode_batch(string_value, frames, db_path)
 		utils.notify("Processed batch", vim.log.levels.DEBUG)
 		globals.clear_typed_letters()
+		typed_letters = globals.get_typed_letters()
+	end
+
+	-- on_key runs before the key does, so this is the state the batch starts in
+	if #typed_letters == 0 then
+		globals.set_batch_recording(vim.fn.reg_recording() ~= "")
 	end
 
 	table.insert(typed_letters, new_key)
+	table.insert(globals.get_typed_modes(), mode)
+	table.insert(globals.get_typed_times(), math.floor(vim.uv.hrtime() / 1000000))
 end
 
 return M
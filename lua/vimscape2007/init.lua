@@ -63,6 +63,11 @@ M.setup = function(opts)
 		utils.notify("Vimscape token logging enabled", vim.log.levels.DEBUG)
 	end
 
+	if config.hardtime then
+		vimscape.enable_hardtime(config.hardtime_max_repeats)
+		utils.notify("Vimscape hardtime mode enabled", vim.log.levels.DEBUG)
+	end
+
 	M.create_user_commands()
 end
 
@@ -141,15 +146,20 @@ M.flush = function()
 	end
 
 	local typed_letters = globals.get_typed_letters()
-	if #typed_letters == 0 then
-		utils.notify("Vimscape: no keystrokes to flush", vim.log.levels.INFO)
-		return
-	end
-
 	local count = #typed_letters
 	local string_value = table.concat(typed_letters)
-	vimscape.process_batch(string_value, get_db_full_path())
+	local frames = keys.encode_frames(typed_letters, globals.get_typed_modes(), globals.get_typed_times())
+
+	-- Always reaches the backend, which may hold the tail of an earlier batch
+	keys.sync_leaders()
+	keys.sync_recording(get_db_full_path())
+	vimscape.flush_mode_batch(string_value, frames, get_db_full_path())
 	globals.clear_typed_letters()
+
+	if count == 0 then
+		utils.notify("Vimscape: no keystrokes to flush", vim.log.levels.INFO)
+		return
+	end
 	utils.notify("Vimscape: flushed " .. count .. " keystrokes", vim.log.levels.INFO)
 end
 
@@ -0,0 +1,138 @@
+//! Typing activity of a batch: actions per minute, idle gaps and active time,
+//! measured from the timestamps the Lua side sends with each key. Insert mode
+//! keys are only sent as timestamps, so typing text counts as active time.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use crate::mode::frames;
+
+// A pause between two keys longer than this is idle time, not editing
+const IDLE_GAP_MS: i64 = 5_000;
+
+// Time of the last key of the previous batch, keyed by database path, so the
+// pause between two batches is measured too
+static LAST_KEY_TIMES: LazyLock<Mutex<HashMap<String, i64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Activity {
+    pub keys: i64,
+    pub active_ms: i64,
+    pub idle_ms: i64,
+    pub idle_gaps: i64,
+}
+
+impl Activity {
+    /// Measure a batch from the time each key was typed at. `previous` is the
+    /// time of the last key before the batch, if any.
+    pub fn measure(times: &[i64], previous: Option<i64>) -> Activity {
+        let mut activity = Activity {
+            keys: i64::try_from(times.len()).unwrap_or(i64::MAX),
+            ..Activity::default()
+        };
+
+        let mut last = previous;
+        for &time in times {
+            if let Some(last) = last {
+                let gap = time.saturating_sub(last).max(0);
+                if gap > IDLE_GAP_MS {
+                    activity.idle_ms += gap;
+                    activity.idle_gaps += 1;
+                } else {
+                    activity.active_ms += gap;
+                }
+            }
+            last = Some(time);
+        }
+        activity
+    }
+
+    /// Keys per minute of active time
+    pub fn apm(&self) -> f64 {
+        if self.active_ms == 0 {
+            return 0.0;
+        }
+        self.keys as f64 * 60_000.0 / self.active_ms as f64
+    }
+}
+
+/// The time of each timed run of the framed batch, see `mode::frames`
+pub fn key_times(encoded: &str) -> Vec<i64> {
+    frames(encoded).filter_map(|frame| frame.time).collect()
+}
+
+/// Measure a batch of this database, remembering its last key for the next
+/// one. None for a batch without timestamps.
+pub fn measure_batch(db_path: &str, times: &[i64]) -> Option<Activity> {
+    let &last_time = times.last()?;
+    let Ok(mut last_key_times) = LAST_KEY_TIMES.lock() else {
+        return Some(Activity::measure(times, None));
+    };
+    let previous = last_key_times.insert(db_path.to_string(), last_time);
+    Some(Activity::measure(times, previous))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_times() {
+        assert_eq!(key_times("n:1:100;no:1:180;c:3:400;"), vec![100, 180, 400]);
+        assert!(key_times("n:1;c:3;").is_empty());
+        assert!(key_times("").is_empty());
+    }
+
+    #[test]
+    fn test_insert_run_is_active_time() {
+        // ihello<Esc>j, the insert mode keys typed 3s apart
+        let frames = "n:1:0;i:0:3000;i:0:6000;i:0:9000;n:1:12000;";
+        let activity = Activity::measure(&key_times(frames), None);
+        assert_eq!(activity.keys, 5);
+        assert_eq!(activity.active_ms, 12_000);
+        assert_eq!(activity.idle_gaps, 0);
+    }
+
+    #[test]
+    fn test_measure_active_time() {
+        let activity = Activity::measure(&[0, 200, 500, 1000], None);
+        assert_eq!(
+            activity,
+            Activity {
+                keys: 4,
+                active_ms: 1000,
+                idle_ms: 0,
+                idle_gaps: 0,
+            }
+        );
+        assert!((activity.apm() - 240.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_measure_idle_gaps() {
+        let activity = Activity::measure(&[0, 500, 20_500, 21_000, 40_000], None);
+        assert_eq!(activity.active_ms, 1000);
+        assert_eq!(activity.idle_ms, 39_000);
+        assert_eq!(activity.idle_gaps, 2);
+    }
+
+    #[test]
+    fn test_measure_from_previous_batch() {
+        assert_eq!(Activity::measure(&[1000], Some(400)).active_ms, 600);
+        assert_eq!(Activity::measure(&[9000], Some(1000)).idle_gaps, 1);
+        // A single key with nothing before it has no duration
+        assert!(Activity::measure(&[1000], None).apm().abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_measure_batch_remembers_last_key() {
+        let db = "activity_test_last_key.db";
+        assert_eq!(measure_batch(db, &[]), None);
+        assert_eq!(
+            measure_batch(db, &[100, 300]).map(|a| a.active_ms),
+            Some(200)
+        );
+        assert_eq!(measure_batch(db, &[700]).map(|a| a.active_ms), Some(400));
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::ops::Range;
 
 use nvim_oxi::{
     Dictionary,
@@ -7,15 +8,17 @@ use nvim_oxi::{
 use rusqlite::Connection;
 
 use crate::{
+    activity, carryover,
     db::{
-        create_tables, get_skill_data, get_skill_details_from_db, write_exp_to_table_tx,
-        write_levels_to_table_tx,
+        create_tables, get_skill_data, get_skill_details_from_db, write_activity_to_table_tx,
+        write_exp_to_table_tx, write_levels_to_table_tx, write_penalties_to_table_tx,
     },
+    hardtime, leader,
     levels::{get_levels_diff, get_updated_levels, notify_level_ups},
     lexer::Lexer,
+    mode::ModeRuns,
     parse_utils::parse_action_into_skill,
     skill_data::{format_skill_data, format_skill_details},
-    token::Token,
     token_log,
 };
 
@@ -33,80 +36,71 @@ pub fn enable_token_log(db_path: String) {
     token_log::enable(&db_path);
 }
 
-/// Strip echoed leader-key sequences from the raw input.
-///
-/// Neovim's `vim.on_key` replays the expansion of custom `<Space>`-leader
-/// mappings, producing a duplicated suffix in the batch string. For example,
-/// pressing `<Space>sf` yields `|space|sf<Space>sf` — the real keystrokes
-/// followed by a literal `<Space>` echo of the same characters.
-///
-/// This function finds every occurrence of `|space|{chars}<Space>{same chars}`
-/// and removes the entire sequence. The binding suffix (`{chars}`) must be
-/// short alphanumeric characters — this prevents false matches against
-/// unrelated `|space|` / `<Space>` pairs separated by other commands.
-fn strip_leader_echoes(input: &str) -> String {
-    const PIPE_SPACE: &str = "|space|";
-    const ANGLE_SPACE: &str = "<Space>";
-    const MAX_BINDING_LEN: usize = 10;
+pub fn enable_hardtime(max_repeats: i32) {
+    hardtime::enable(max_repeats);
+}
 
-    let mut result = String::with_capacity(input.len());
-    let mut remaining = input;
-
-    while let Some(pipe_pos) = remaining.find(PIPE_SPACE) {
-        // Copy everything before this |space| verbatim (but don't copy |space| yet)
-        result.push_str(&remaining[..pipe_pos]);
-        remaining = &remaining[pipe_pos + PIPE_SPACE.len()..];
-
-        // Extract the binding suffix: the short alphanumeric chars immediately
-        // after |space| that form the leader keybinding (e.g., "sf" in |space|sf)
-        let binding_len = remaining
-            .chars()
-            .take(MAX_BINDING_LEN)
-            .take_while(char::is_ascii_alphanumeric)
-            .count();
-
-        if binding_len > 0 {
-            let binding = &remaining[..binding_len];
-            let after_binding = &remaining[binding_len..];
-
-            // Check if <Space>{binding} immediately follows the binding chars
-            let echo = format!("{ANGLE_SPACE}{binding}");
-            if let Some(after_echo) = after_binding.strip_prefix(echo.as_str()) {
-                // Leader echo confirmed — drop the entire |space|{chars}<Space>{chars}
-                remaining = after_echo;
-                continue;
-            }
-        }
+/// Set the raw `mapleader` and `maplocalleader` values whose echoes are
+/// stripped from batches
+pub fn set_leaders((leader, localleader): (String, String)) {
+    leader::set(&[leader, localleader]);
+}
 
-        // Not a leader echo — put |space| back and continue
-        result.push_str(PIPE_SPACE);
+/// Cut the byte ranges (sorted, not overlapping) out of `input`
+fn remove_ranges(input: &str, ranges: &[Range<usize>]) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut kept_from = 0;
+    for range in ranges {
+        result.push_str(&input[kept_from..range.start]);
+        kept_from = range.end;
     }
-
-    // Append any remaining input after the last match (or all of it if no match)
-    result.push_str(remaining);
+    result.push_str(&input[kept_from..]);
     result
 }
 
-/// Remove consecutive duplicate `CameraMovement` tokens.
-///
-/// `vim.on_key` can fire twice for multi-character commands like `zz`/`zt`/`zb`,
-/// producing duplicate tokens in the batch. This removes every second consecutive
-/// `CameraMovement` to compensate.
-fn dedup_tokens(tokens: &mut Vec<Token>) {
-    let mut i = 1;
-    while i < tokens.len() {
-        if tokens[i] == Token::CameraMovement && tokens[i - 1] == Token::CameraMovement {
-            tokens.remove(i);
-            i += 1; // skip past the survivor to avoid re-matching
-        } else {
-            i += 1;
-        }
-    }
+/// Set whether a macro was recording when the next batch started, so the
+/// lexer does not carry a wrong guess from earlier batches
+pub fn set_recording((recording, db_path): (bool, String)) {
+    carryover::set_recording(&db_path, recording);
 }
 
+/// Process a full batch of keys. A command cut off at the end of the batch is
+/// held back and lexed together with the next batch.
 pub fn process_batch((input, db_path): (String, String)) -> bool {
-    let input = strip_leader_echoes(&input);
-    let mut lexer = Lexer::new(&input);
+    run_batch(&input, "", &db_path, true)
+}
+
+/// Process the keys typed so far and anything held back from earlier batches,
+/// leaving nothing pending. Used for `:Vimscape flush` and on exit.
+pub fn flush_batch((input, db_path): (String, String)) -> bool {
+    run_batch(&input, "", &db_path, false)
+}
+
+/// Like `process_batch`, with the Neovim mode each key was typed in framed as
+/// `{mode}:{bytes};` runs (see `ModeRuns`), so the lexer never has to guess
+/// which mode a key belongs to. Runs framed as `{mode}:{bytes}:{ms};` also
+/// carry the time their key was typed at, and the batch's activity is stored.
+pub fn process_mode_batch((input, frames, db_path): (String, String, String)) -> bool {
+    run_batch(&input, &frames, &db_path, true)
+}
+
+/// Like `flush_batch`, with the keys framed as for `process_mode_batch`
+pub fn flush_mode_batch((input, frames, db_path): (String, String, String)) -> bool {
+    run_batch(&input, &frames, &db_path, false)
+}
+
+fn run_batch(input: &str, frames: &str, db_path: &str, defer_incomplete: bool) -> bool {
+    let checkpoint = carryover::take(db_path);
+    let input = format!("{}{input}", checkpoint.pending());
+    let mut input_modes = checkpoint.pending_modes().clone();
+    input_modes.append(checkpoint.pending().len(), &ModeRuns::decode(frames));
+    let activity = activity::measure_batch(db_path, &activity::key_times(frames));
+
+    let echoes = leader::echo_ranges(&input, &leader::current(), &input_modes);
+    let input = remove_ranges(&input, &echoes);
+    input_modes.remove_ranges(&echoes);
+
+    let mut lexer = Lexer::resume(&input, checkpoint, defer_incomplete).with_modes(input_modes);
     let mut skills: HashMap<String, i32> = HashMap::new();
     let logging = token_log::is_enabled();
 
@@ -114,19 +108,26 @@ pub fn process_batch((input, db_path): (String, String)) -> bool {
         token_log::log_batch(&input);
     }
 
-    // Collect all tokens, then dedup before processing
     let mut tokens = Vec::new();
-    while let Some(token) = lexer.next_token() {
+    while let Some(spanned) = lexer.next_token_with_span() {
         if logging {
-            token_log::log_token(&token);
+            token_log::log_token(&spanned, &input);
         }
-        tokens.push(token);
+        tokens.push(spanned.token);
     }
+    carryover::store(db_path, lexer.checkpoint());
 
-    dedup_tokens(&mut tokens);
+    // Hardtime mode takes XP away from repeated hjkl and records every
+    // anti-pattern as a penalty
+    let penalties = if hardtime::is_enabled() {
+        hardtime::mark_repeated_motions(&mut tokens, hardtime::max_repeats());
+        hardtime::count_anti_patterns(&tokens)
+    } else {
+        HashMap::new()
+    };
 
     for token in &tokens {
-        if let Some(result) = parse_action_into_skill(token) {
+        for result in parse_action_into_skill(token) {
             let skill_str = result.to_str();
             let new_exp = result.get_exp_from_skill();
             match skills.get(&*skill_str) {
@@ -136,7 +137,7 @@ pub fn process_batch((input, db_path): (String, String)) -> bool {
         }
     }
 
-    let Ok(conn) = Connection::open(&db_path) else {
+    let Ok(conn) = Connection::open(db_path) else {
         notify_error("[vimscape] Failed to connect to database");
         return false;
     };
@@ -147,6 +148,7 @@ pub fn process_batch((input, db_path): (String, String)) -> bool {
         return false;
     }
 
+    let batch_exp: i32 = skills.values().sum();
     let updated_levels = get_updated_levels(&skill_data, &skills);
     let levels_diff = get_levels_diff(&skill_data, &updated_levels);
 
@@ -165,6 +167,14 @@ pub fn process_batch((input, db_path): (String, String)) -> bool {
     if !write_exp_to_table_tx(&tx, skills) {
         return false;
     }
+    if !penalties.is_empty() && !write_penalties_to_table_tx(&tx, &penalties) {
+        return false;
+    }
+    if let Some(activity) = activity
+        && !write_activity_to_table_tx(&tx, &activity, batch_exp)
+    {
+        return false;
+    }
 
     if let Err(e) = tx.commit() {
         notify_error(&format!("[vimscape] Commit failed: {e}"));
@@ -209,182 +219,3 @@ pub fn get_skill_details((c_word, db_path): (String, String)) -> Vec<String> {
         Vec::new()
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::lexer::Lexer;
-
-    /// Helper: lex input string into tokens, then run dedup
-    fn lex_and_dedup(input: &str) -> Vec<Token> {
-        let mut lexer = Lexer::new(input);
-        let mut tokens = Vec::new();
-        while let Some(token) = lexer.next_token() {
-            tokens.push(token);
-        }
-        dedup_tokens(&mut tokens);
-        tokens
-    }
-
-    /// Helper: count occurrences of `CameraMovement` in a token vec
-    fn count_camera(tokens: &[Token]) -> usize {
-        tokens
-            .iter()
-            .filter(|t| **t == Token::CameraMovement)
-            .count()
-    }
-
-    #[test]
-    fn test_dedup_single_zz() {
-        // One zz command, no duplication — should remain 1
-        let tokens = lex_and_dedup("zz");
-        assert_eq!(count_camera(&tokens), 1);
-    }
-
-    #[test]
-    fn test_dedup_duplicated_zz() {
-        // zz duplicated by vim.on_key: zzzz → 2 CameraMovement → dedup to 1
-        let tokens = lex_and_dedup("zzzz");
-        assert_eq!(count_camera(&tokens), 1);
-    }
-
-    #[test]
-    fn test_dedup_duplicated_zt() {
-        // zt duplicated: ztzt → 2 CameraMovement → dedup to 1
-        let tokens = lex_and_dedup("ztzt");
-        assert_eq!(count_camera(&tokens), 1);
-    }
-
-    #[test]
-    fn test_dedup_duplicated_zb() {
-        // zb duplicated: zbzb → 2 CameraMovement → dedup to 1
-        let tokens = lex_and_dedup("zbzb");
-        assert_eq!(count_camera(&tokens), 1);
-    }
-
-    #[test]
-    fn test_dedup_all_three_duplicated() {
-        // zz, zt, zb each duplicated: zzzzztztzbzb → 6 CameraMovement → dedup to 3
-        let tokens = lex_and_dedup("zzzzztztzbzb");
-        assert_eq!(count_camera(&tokens), 3);
-    }
-
-    #[test]
-    fn test_dedup_non_consecutive_camera() {
-        // zz, then j, then zz — not consecutive, both kept
-        let tokens = lex_and_dedup("zzjzz");
-        assert_eq!(count_camera(&tokens), 2);
-    }
-
-    #[test]
-    fn test_dedup_mixed_with_duplicated() {
-        // Duplicated zz, then j, then duplicated zt
-        let tokens = lex_and_dedup("zzzzjztzt");
-        assert_eq!(count_camera(&tokens), 2);
-        assert_eq!(tokens.len(), 3); // CM, MoveVerticalBasic, CM
-    }
-
-    #[test]
-    fn test_dedup_preserves_other_tokens() {
-        // No camera movements, just normal commands — nothing should change
-        let tokens = lex_and_dedup("jjkk5w");
-        assert_eq!(count_camera(&tokens), 0);
-        assert_eq!(tokens.len(), 5);
-    }
-
-    #[test]
-    fn test_dedup_empty() {
-        let tokens = lex_and_dedup("");
-        assert!(tokens.is_empty());
-    }
-
-    #[test]
-    fn test_dedup_three_consecutive_camera() {
-        // 3 consecutive CameraMovement: first pair deduped, third survives
-        // This simulates: one real zz (duplicated to zzzz) + one real zt (not duplicated)
-        // Input "zzzzzt" → lexer produces CM, CM, CM → dedup removes second → CM, CM
-        let tokens = lex_and_dedup("zzzzzt");
-        assert_eq!(count_camera(&tokens), 2);
-    }
-
-    // --- strip_leader_echoes tests ---
-
-    #[test]
-    fn test_strip_leader_basic() {
-        // <Space>sf echoed as |space|sf<Space>sf → entire sequence removed
-        let input = "|space|sf<Space>sf";
-        assert_eq!(strip_leader_echoes(input), "");
-    }
-
-    #[test]
-    fn test_strip_leader_single_char() {
-        // Single-char leader binding: <Space>e → |space|e<Space>e
-        let input = "|space|e<Space>e";
-        assert_eq!(strip_leader_echoes(input), "");
-    }
-
-    #[test]
-    fn test_strip_leader_longer_binding() {
-        // Longer binding: <Space>abc → |space|abc<Space>abc
-        let input = "|space|abc<Space>abc";
-        assert_eq!(strip_leader_echoes(input), "");
-    }
-
-    #[test]
-    fn test_strip_leader_with_surrounding_keys() {
-        // Leader echo embedded within other keystrokes
-        let input = "jjk|space|sf<Space>sfhhl";
-        assert_eq!(strip_leader_echoes(input), "jjkhhl");
-    }
-
-    #[test]
-    fn test_strip_leader_multiple_echoes() {
-        // Two separate leader echoes in one batch
-        let input = "|space|sf<Space>sfjj|space|ab<Space>ab";
-        assert_eq!(strip_leader_echoes(input), "jj");
-    }
-
-    #[test]
-    fn test_strip_leader_no_echo() {
-        // |space| without a matching <Space> echo — left untouched
-        let input = "|space|sfjjkk";
-        assert_eq!(strip_leader_echoes(input), "|space|sfjjkk");
-    }
-
-    #[test]
-    fn test_strip_leader_no_space_at_all() {
-        // No |space| in input — returned unchanged
-        let input = "jjkkhh";
-        assert_eq!(strip_leader_echoes(input), "jjkkhh");
-    }
-
-    #[test]
-    fn test_strip_leader_empty_input() {
-        assert_eq!(strip_leader_echoes(""), "");
-    }
-
-    #[test]
-    fn test_strip_leader_mismatch_not_stripped() {
-        // <Space> is present but the chars after it don't match — no stripping
-        let input = "|space|sf<Space>xy";
-        assert_eq!(strip_leader_echoes(input), "|space|sf<Space>xy");
-    }
-
-    #[test]
-    fn test_strip_leader_later_space_not_consumed_by_earlier_mismatch() {
-        // Regression: multiple |space| where the first has no <Space> echo but
-        // a later one does. The mismatch branch must not consume the <Space>
-        // so the correct |space| can still match against it.
-        let input = ":w|enter|<C-D><C-D><C-U><C-U>yyyy110yyGG|space|ss|space|s|escape|x|space|sw<Space>sw|escape|:q|escape|:Vimsacpe|backspace||backspace||backspace||backspace|cape|space|flush|enter|";
-        let expected = ":w|enter|<C-D><C-D><C-U><C-U>yyyy110yyGG|space|ss|space|s|escape|x|escape|:q|escape|:Vimsacpe|backspace||backspace||backspace||backspace|cape|space|flush|enter|";
-        assert_eq!(strip_leader_echoes(input), expected);
-    }
-
-    #[test]
-    fn test_strip_leader_preserves_pipe_space_in_commands() {
-        // |space| inside a command (e.g., :Vimscape|space|toggle) — no <Space> echo,
-        // so it passes through untouched
-        let input = ":Vimscape|space|toggle|enter|";
-        assert_eq!(strip_leader_echoes(input), ":Vimscape|space|toggle|enter|");
-    }
-}
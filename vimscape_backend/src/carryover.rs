@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use crate::lexer::Checkpoint;
+
+// Lexer checkpoints keyed by database path, so a command split across two
+// batches is lexed as one
+static CARRYOVER: LazyLock<Mutex<HashMap<String, Checkpoint>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Take the checkpoint left by the previous batch for this database.
+pub fn take(db_path: &str) -> Checkpoint {
+    CARRYOVER
+        .lock()
+        .ok()
+        .and_then(|mut carried| carried.remove(db_path))
+        .unwrap_or_default()
+}
+
+pub fn store(db_path: &str, checkpoint: Checkpoint) {
+    if let Ok(mut carried) = CARRYOVER.lock() {
+        carried.insert(db_path.to_string(), checkpoint);
+    }
+}
+
+/// Record whether a macro was recording when the next batch for this
+/// database started, as Neovim's `reg_recording()` reports it
+pub fn set_recording(db_path: &str, recording: bool) {
+    if let Ok(mut carried) = CARRYOVER.lock() {
+        carried
+            .entry(db_path.to_string())
+            .or_default()
+            .set_recording_macro(recording);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::token::Token;
+
+    fn lex(input: &str, checkpoint: Checkpoint) -> (Vec<Token>, Checkpoint) {
+        let mut lexer = Lexer::resume(input, checkpoint, true);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token() {
+            tokens.push(token);
+        }
+        (tokens, lexer.checkpoint())
+    }
+
+    #[test]
+    fn test_take_defaults_to_empty_checkpoint() {
+        assert!(take("carryover_test_missing.db").pending().is_empty());
+    }
+
+    #[test]
+    fn test_store_and_take_are_per_database() {
+        let mut lexer = Lexer::resume("jd", Checkpoint::default(), true);
+        while lexer.next_token().is_some() {}
+        store("carryover_test_a.db", lexer.checkpoint());
+
+        assert!(take("carryover_test_b.db").pending().is_empty());
+        assert_eq!(take("carryover_test_a.db").pending(), "d");
+        // Taking clears it
+        assert!(take("carryover_test_a.db").pending().is_empty());
+    }
+
+    #[test]
+    fn test_reported_recording_state_carries_over() {
+        // `qjj` that closed a window is guessed to start a recording
+        let (tokens, checkpoint) = lex("qjj", Checkpoint::default());
+        assert_eq!(
+            tokens,
+            vec![Token::MacroRecordStart, Token::MoveVerticalBasic(1)]
+        );
+        store("carryover_test_recording.db", checkpoint);
+
+        // Neovim reports no recording, so the next `qa` starts one
+        set_recording("carryover_test_recording.db", false);
+        let (tokens, checkpoint) = lex("qaq", take("carryover_test_recording.db"));
+        assert_eq!(
+            tokens,
+            vec![Token::MacroRecordStart, Token::MacroRecordStop]
+        );
+        store("carryover_test_recording.db", checkpoint);
+
+        // A recording Neovim reports is stopped by the first bare `q`
+        set_recording("carryover_test_recording.db", true);
+        let (tokens, _) = lex("q", take("carryover_test_recording.db"));
+        assert_eq!(tokens, vec![Token::MacroRecordStop]);
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use rusqlite::{Connection, Transaction, params};
 
-use crate::{skill_data::SkillData, skills::Skills};
+use crate::{activity::Activity, skill_data::SkillData, skills::Skills};
 
 pub fn get_skill_data(conn: &Connection) -> Vec<SkillData> {
     let mut statement = match conn.prepare("SELECT name, exp, level FROM skills") {
@@ -36,9 +36,50 @@ pub fn create_tables(conn: &Connection) -> bool {
     if !create_skills_table(conn) {
         return false;
     }
+    if !create_penalties_table(conn) {
+        return false;
+    }
+    if !create_activity_table(conn) {
+        return false;
+    }
     populate_skills_enum_table(conn)
 }
 
+fn create_penalties_table(conn: &Connection) -> bool {
+    if let Err(e) = conn.execute(
+        "CREATE TABLE IF NOT EXISTS penalties (
+          name TEXT PRIMARY KEY,
+          count INTEGER NOT NULL DEFAULT 0
+         )",
+        (),
+    ) {
+        eprintln!("[vimscape] Create table failed: {e}");
+        return false;
+    }
+    true
+}
+
+// One row per timed batch, for APM and XP/hr readouts
+fn create_activity_table(conn: &Connection) -> bool {
+    if let Err(e) = conn.execute(
+        "CREATE TABLE IF NOT EXISTS activity (
+          id INTEGER PRIMARY KEY,
+          recorded_at INTEGER NOT NULL DEFAULT (unixepoch()),
+          keys INTEGER NOT NULL,
+          active_ms INTEGER NOT NULL,
+          idle_ms INTEGER NOT NULL,
+          idle_gaps INTEGER NOT NULL,
+          apm REAL NOT NULL,
+          exp INTEGER NOT NULL
+         )",
+        (),
+    ) {
+        eprintln!("[vimscape] Create table failed: {e}");
+        return false;
+    }
+    true
+}
+
 fn create_skills_table(conn: &Connection) -> bool {
     if let Err(e) = conn.execute(
         "CREATE TABLE IF NOT EXISTS skills (
@@ -55,6 +96,9 @@ fn create_skills_table(conn: &Connection) -> bool {
     true
 }
 
+/// Seed one row per skill. Skills are keyed by their position in
+/// `Skills::to_str_vec`, so skills appended to the enum are inserted into
+/// existing databases while rows that already exist keep their XP and level.
 fn populate_skills_enum_table(conn: &Connection) -> bool {
     for (i, skill) in Skills::to_str_vec().iter().enumerate() {
         if let Err(e) = conn.execute(
@@ -157,6 +201,80 @@ pub fn write_levels_to_table_tx(tx: &Transaction, levels_diff: &HashMap<String,
     true
 }
 
+pub fn write_penalties_to_table_tx(tx: &Transaction, penalties: &HashMap<String, i32>) -> bool {
+    let mut stmt = match tx.prepare_cached(
+        "INSERT INTO penalties (name, count) VALUES (?1, ?2)
+         ON CONFLICT(name) DO UPDATE SET count = count + excluded.count",
+    ) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("[vimscape] Prepare failed: {e}");
+            return false;
+        }
+    };
+
+    for (key, count) in penalties {
+        if let Err(e) = stmt.execute(params![key, count]) {
+            eprintln!("[vimscape] Update penalty failed for {key}: {e}");
+        }
+    }
+
+    true
+}
+
+pub fn write_activity_to_table_tx(tx: &Transaction, activity: &Activity, exp: i32) -> bool {
+    if let Err(e) = tx.execute(
+        "INSERT INTO activity (keys, active_ms, idle_ms, idle_gaps, apm, exp)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            activity.keys,
+            activity.active_ms,
+            activity.idle_ms,
+            activity.idle_gaps,
+            activity.apm(),
+            exp
+        ],
+    ) {
+        eprintln!("[vimscape] Insert activity failed: {e}");
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+pub fn get_activity_data(conn: &Connection) -> Vec<(Activity, i32)> {
+    let Ok(mut statement) =
+        conn.prepare("SELECT keys, active_ms, idle_ms, idle_gaps, exp FROM activity ORDER BY id")
+    else {
+        return Vec::new();
+    };
+    let Ok(rows) = statement.query_map([], |row| {
+        Ok((
+            Activity {
+                keys: row.get(0)?,
+                active_ms: row.get(1)?,
+                idle_ms: row.get(2)?,
+                idle_gaps: row.get(3)?,
+            },
+            row.get(4)?,
+        ))
+    }) else {
+        return Vec::new();
+    };
+    rows.filter_map(std::result::Result::ok).collect()
+}
+
+#[cfg(test)]
+pub fn get_penalty_data(conn: &Connection) -> HashMap<String, i32> {
+    let Ok(mut statement) = conn.prepare("SELECT name, count FROM penalties") else {
+        return HashMap::new();
+    };
+    let Ok(rows) = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?))) else {
+        return HashMap::new();
+    };
+    rows.filter_map(std::result::Result::ok).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -427,4 +545,97 @@ mod tests {
             "Should have correct number of skills after double creation"
         );
     }
+
+    #[test]
+    fn test_create_tables_adds_new_skills_to_existing_database() {
+        let conn = Connection::open_in_memory().expect("Failed to create in-memory database");
+        assert!(create_skills_table(&conn));
+
+        // Database created before Macros existed, with some progress
+        let legacy_skills = [
+            "VerticalNavigation",
+            "HorizontalNavigation",
+            "CodeFlow",
+            "CameraMovement",
+            "WindowManagement",
+            "TextManipulation",
+            "Clipboard",
+            "Finesse",
+            "Search",
+            "Knowledge",
+            "Saving",
+        ];
+        for (i, skill) in legacy_skills.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO skills (id, name, exp, level) VALUES (?1, ?2, 500, 5)",
+                params![i, skill],
+            )
+            .expect("Insert legacy skill");
+        }
+
+        assert!(create_tables(&conn));
+
+        let skills = get_skill_data(&conn);
+        assert_eq!(skills.len(), Skills::to_str_vec().len());
+
+        for new_skill in ["Macros", "BufferManagement", "Debugging", "Diffing"] {
+            let skill = skills.iter().find(|s| s.skill_name == new_skill);
+            assert_eq!(
+                skill.map(|s| (s.total_exp, s.level)),
+                Some((0, 1)),
+                "{new_skill} should be added with no progress"
+            );
+        }
+
+        let nav_skill = skills.iter().find(|s| s.skill_name == "VerticalNavigation");
+        assert_eq!(
+            nav_skill.map(|s| (s.total_exp, s.level)),
+            Some((500, 5)),
+            "Existing progress should be kept"
+        );
+    }
+
+    #[test]
+    fn test_write_penalties_accumulates_counts() {
+        let mut conn = setup_test_db();
+
+        let mut penalties = HashMap::new();
+        penalties.insert("ArrowKey".to_string(), 3);
+
+        let tx = conn.transaction().expect("Failed to start transaction");
+        assert!(write_penalties_to_table_tx(&tx, &penalties));
+        assert!(write_penalties_to_table_tx(&tx, &penalties));
+        tx.commit().expect("Failed to commit transaction");
+
+        let stored = get_penalty_data(&conn);
+        assert_eq!(stored.get("ArrowKey"), Some(&6));
+        assert_eq!(stored.len(), 1);
+    }
+
+    #[test]
+    fn test_write_activity_appends_rows() {
+        let mut conn = setup_test_db();
+        let activity = Activity {
+            keys: 120,
+            active_ms: 30_000,
+            idle_ms: 8_000,
+            idle_gaps: 1,
+        };
+
+        let tx = conn.transaction().expect("Failed to start transaction");
+        assert!(write_activity_to_table_tx(&tx, &activity, 450));
+        assert!(write_activity_to_table_tx(&tx, &Activity::default(), 0));
+        tx.commit().expect("Failed to commit transaction");
+
+        assert_eq!(
+            get_activity_data(&conn),
+            vec![(activity, 450), (Activity::default(), 0)]
+        );
+        let apm: f64 = conn
+            .query_row("SELECT apm FROM activity WHERE id = 1", [], |row| {
+                row.get(0)
+            })
+            .expect("Activity row should exist");
+        assert!((apm - 240.0).abs() < f64::EPSILON);
+    }
 }
@@ -0,0 +1,276 @@
+//! Keys `vim.on_key` reports twice
+//!
+//! Neovim reports some keys a second time right after they are typed: the
+//! motion typed after an operator (`dw` arrives as `dww`) and the `z` scrolling
+//! commands (`zz` arrives as `zzzz`). `ECHOES` lists the commands known to be
+//! echoed and which part of their keys is repeated. The lexer drops the copy
+//! wherever a rule expects one, so an echoing command needs a table entry
+//! rather than its own workaround.
+
+use crate::token::Token;
+
+/// The part of a command's keys that is reported again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Echo {
+    /// The whole command, count included (`zz` → `zzzz`, `zt` → `ztzt`)
+    Command,
+    /// An operator's motion or text object, up to a find target
+    /// (`dw` → `dww`, `ciw` → `ciwiw`, `dfa` → `dffa`, `dd` → `ddd`)
+    Motion,
+    /// An operator's motion count, the motion after it is not echoed then
+    /// (`d3w` → `d33w`)
+    MotionCount,
+}
+
+pub struct EchoRule {
+    pub echo: Echo,
+    /// Whether the rule covers a command, given its token and the keys it
+    /// was lexed from (only the operator keys for an unfinished operator)
+    pub applies: fn(&Token, &str) -> bool,
+    /// An example as typed and as `vim.on_key` reports it, checked by the tests
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub typed: &'static str,
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub reported: &'static str,
+}
+
+pub const ECHOES: &[EchoRule] = &[
+    EchoRule {
+        echo: Echo::Command,
+        applies: z_command,
+        typed: "zz",
+        reported: "zzzz",
+    },
+    EchoRule {
+        echo: Echo::Command,
+        applies: z_command,
+        typed: "zt",
+        reported: "ztzt",
+    },
+    EchoRule {
+        echo: Echo::Command,
+        applies: z_command,
+        typed: "zb",
+        reported: "zbzb",
+    },
+    EchoRule {
+        echo: Echo::Motion,
+        applies: operator,
+        typed: "dd",
+        reported: "ddd",
+    },
+    EchoRule {
+        echo: Echo::Motion,
+        applies: operator,
+        typed: "3dw",
+        reported: "3dww",
+    },
+    EchoRule {
+        echo: Echo::Motion,
+        applies: operator,
+        typed: "ciw",
+        reported: "ciwiw",
+    },
+    EchoRule {
+        echo: Echo::Motion,
+        applies: operator,
+        typed: "dfa",
+        reported: "dffa",
+    },
+    EchoRule {
+        echo: Echo::Motion,
+        applies: operator,
+        typed: "dgg",
+        reported: "dgggg",
+    },
+    EchoRule {
+        echo: Echo::Motion,
+        applies: operator,
+        typed: "g~w",
+        reported: "g~ww",
+    },
+    EchoRule {
+        echo: Echo::MotionCount,
+        applies: operator,
+        typed: "d3w",
+        reported: "d33w",
+    },
+    EchoRule {
+        echo: Echo::MotionCount,
+        applies: operator,
+        typed: "y12j",
+        reported: "y1212j",
+    },
+];
+
+// zz, zt and zb, but not <C-E> and <C-Y>. Fold commands are not echoed, so
+// zjzj moves over two folds.
+fn z_command(token: &Token, keys: &str) -> bool {
+    matches!(token, Token::CameraMovement)
+        && keys
+            .trim_start_matches(|ch: char| ch.is_ascii_digit())
+            .starts_with('z')
+}
+
+fn operator(token: &Token, _keys: &str) -> bool {
+    matches!(token, Token::Operator(_))
+}
+
+/// Whether the command lexed as `token` from `keys` is reported with `echo`
+pub fn echoes(token: &Token, keys: &str, echo: Echo) -> bool {
+    ECHOES
+        .iter()
+        .any(|rule| rule.echo == echo && (rule.applies)(token, keys))
+}
+
+/// The first half of `keys` when the second half repeats it (`33` → `3`)
+pub fn unrepeat(keys: &str) -> &str {
+    let (first, second) = keys.split_at(keys.len() / 2);
+    if !first.is_empty() && first == second {
+        first
+    } else {
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn lex(input: &str) -> Vec<Token> {
+        let mut lexer = Lexer::new(input);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token() {
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    /// Helper: count occurrences of `CameraMovement` in a token vec
+    fn count_camera(tokens: &[Token]) -> usize {
+        tokens
+            .iter()
+            .filter(|t| **t == Token::CameraMovement)
+            .count()
+    }
+
+    #[test]
+    fn test_every_rule_lexes_like_the_typed_keys() {
+        for rule in ECHOES {
+            let typed = lex(rule.typed);
+            assert_eq!(typed.len(), 1, "{} should be one command", rule.typed);
+            assert_eq!(lex(rule.reported), typed, "{} as reported", rule.typed);
+            assert!(
+                echoes(&typed[0], rule.typed, rule.echo),
+                "{} should match its own rule",
+                rule.typed
+            );
+        }
+    }
+
+    #[test]
+    fn test_unrepeat() {
+        assert_eq!(unrepeat("33"), "3");
+        assert_eq!(unrepeat("1212"), "12");
+        assert_eq!(unrepeat("3"), "3");
+        assert_eq!(unrepeat("123"), "123");
+        assert_eq!(unrepeat("1213"), "1213");
+        assert_eq!(unrepeat(""), "");
+    }
+
+    #[test]
+    fn test_control_scrolling_is_not_a_z_command() {
+        assert!(!echoes(&Token::CameraMovement, "<C-E>", Echo::Command));
+        assert_eq!(lex("<C-E><C-E>").len(), 2);
+    }
+
+    #[test]
+    fn test_dedup_single_zz() {
+        // One zz command, no duplication — should remain 1
+        let tokens = lex("zz");
+        assert_eq!(count_camera(&tokens), 1);
+    }
+
+    #[test]
+    fn test_dedup_duplicated_zz() {
+        // zz duplicated by vim.on_key: zzzz → 1 CameraMovement
+        let tokens = lex("zzzz");
+        assert_eq!(count_camera(&tokens), 1);
+    }
+
+    #[test]
+    fn test_dedup_duplicated_zt() {
+        let tokens = lex("ztzt");
+        assert_eq!(count_camera(&tokens), 1);
+    }
+
+    #[test]
+    fn test_dedup_duplicated_zb() {
+        let tokens = lex("zbzb");
+        assert_eq!(count_camera(&tokens), 1);
+    }
+
+    #[test]
+    fn test_dedup_all_three_duplicated() {
+        // zz, zt, zb each duplicated: zzzzztztzbzb → 3 CameraMovement
+        let tokens = lex("zzzzztztzbzb");
+        assert_eq!(count_camera(&tokens), 3);
+    }
+
+    #[test]
+    fn test_dedup_non_consecutive_camera() {
+        // zz, then j, then zz — not consecutive, both kept
+        let tokens = lex("zzjzz");
+        assert_eq!(count_camera(&tokens), 2);
+    }
+
+    #[test]
+    fn test_dedup_mixed_with_duplicated() {
+        // Duplicated zz, then j, then duplicated zt
+        let tokens = lex("zzzzjztzt");
+        assert_eq!(count_camera(&tokens), 2);
+        assert_eq!(tokens.len(), 3); // CM, MoveVerticalBasic, CM
+    }
+
+    #[test]
+    fn test_dedup_preserves_other_tokens() {
+        // No camera movements, just normal commands — nothing should change
+        let tokens = lex("jjkk5w");
+        assert_eq!(count_camera(&tokens), 0);
+        assert_eq!(tokens.len(), 5);
+    }
+
+    #[test]
+    fn test_dedup_empty() {
+        assert!(lex("").is_empty());
+    }
+
+    #[test]
+    fn test_dedup_three_consecutive_camera() {
+        // One real zz (duplicated to zzzz) + one real zt (not duplicated)
+        let tokens = lex("zzzzzt");
+        assert_eq!(count_camera(&tokens), 2);
+    }
+
+    #[test]
+    fn test_dedup_different_camera_commands_kept() {
+        // zz then zt are different commands even though both are CameraMovement
+        let tokens = lex("zzzt");
+        assert_eq!(count_camera(&tokens), 2);
+    }
+
+    #[test]
+    fn test_repeated_fold_commands_are_kept() {
+        assert_eq!(lex("zjzj"), vec![Token::FoldMove(1), Token::FoldMove(1)]);
+        assert_eq!(lex("zaza"), vec![Token::FoldToggle, Token::FoldToggle]);
+        assert_eq!(lex("zdzd"), vec![Token::FoldDelete, Token::FoldDelete]);
+    }
+
+    #[test]
+    fn test_dedup_keeps_different_z_commands() {
+        // zz then za are different commands, both kept
+        let tokens = lex("zzza");
+        assert_eq!(tokens, vec![Token::CameraMovement, Token::FoldToggle]);
+    }
+}
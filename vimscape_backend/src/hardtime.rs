@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use crate::token::{AntiPattern, Token};
+
+struct HardtimeConfig {
+    enabled: bool,
+    max_repeats: usize,
+}
+
+static HARDTIME_CONFIG: LazyLock<Mutex<HardtimeConfig>> = LazyLock::new(|| {
+    Mutex::new(HardtimeConfig {
+        enabled: false,
+        max_repeats: 3,
+    })
+});
+
+pub fn enable(max_repeats: i32) {
+    if let Ok(mut config) = HARDTIME_CONFIG.lock() {
+        config.enabled = true;
+        config.max_repeats = usize::try_from(max_repeats).unwrap_or(0);
+    }
+}
+
+pub fn is_enabled() -> bool {
+    HARDTIME_CONFIG.lock().is_ok_and(|config| config.enabled)
+}
+
+pub fn max_repeats() -> usize {
+    HARDTIME_CONFIG
+        .lock()
+        .map_or(3, |config| config.max_repeats)
+}
+
+/// Replace single-step `hjkl` presses beyond `max_repeats` in a row with
+/// `AntiPattern::RepeatedMotion`.
+///
+/// `jjjjj` with a limit of 3 keeps the first three moves and flags the last
+/// two, so they earn no XP. Counted motions (`5j`) are never flagged.
+pub fn mark_repeated_motions(tokens: &mut [Token], max_repeats: usize) {
+    let mut run_len = 0;
+    let mut previous: Option<Token> = None;
+
+    for token in tokens.iter_mut() {
+        let is_single_step = matches!(
+            token,
+            Token::MoveVerticalBasic(1) | Token::MoveHorizontalBasic(1)
+        );
+
+        if !is_single_step {
+            run_len = 0;
+            previous = None;
+            continue;
+        }
+
+        if previous.as_ref() == Some(token) {
+            run_len += 1;
+        } else {
+            run_len = 1;
+            previous = Some(token.clone());
+        }
+
+        if run_len > max_repeats {
+            *token = Token::AntiPattern(AntiPattern::RepeatedMotion);
+        }
+    }
+}
+
+/// Count anti-pattern tokens by name, for the penalties table.
+pub fn count_anti_patterns(tokens: &[Token]) -> HashMap<String, i32> {
+    let mut penalties: HashMap<String, i32> = HashMap::new();
+    for token in tokens {
+        if let Token::AntiPattern(pattern) = token {
+            *penalties.entry(pattern.to_str().to_string()).or_insert(0) += 1;
+        }
+    }
+    penalties
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_within_limit_untouched() {
+        let mut tokens = vec![Token::MoveVerticalBasic(1); 3];
+        mark_repeated_motions(&mut tokens, 3);
+        assert_eq!(tokens, vec![Token::MoveVerticalBasic(1); 3]);
+    }
+
+    #[test]
+    fn test_repeats_beyond_limit_flagged() {
+        let mut tokens = vec![Token::MoveVerticalBasic(1); 5];
+        mark_repeated_motions(&mut tokens, 3);
+        assert_eq!(tokens[2], Token::MoveVerticalBasic(1));
+        assert_eq!(tokens[3], Token::AntiPattern(AntiPattern::RepeatedMotion));
+        assert_eq!(tokens[4], Token::AntiPattern(AntiPattern::RepeatedMotion));
+    }
+
+    #[test]
+    fn test_run_resets_on_other_tokens() {
+        let mut tokens = vec![
+            Token::MoveHorizontalBasic(1),
+            Token::MoveHorizontalBasic(1),
+            Token::MoveVerticalBasic(1),
+            Token::MoveHorizontalBasic(1),
+            Token::MoveHorizontalBasic(1),
+        ];
+        let expected = tokens.clone();
+        mark_repeated_motions(&mut tokens, 2);
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_counted_motions_not_flagged() {
+        let mut tokens = vec![Token::MoveVerticalBasic(5); 6];
+        mark_repeated_motions(&mut tokens, 1);
+        assert_eq!(tokens, vec![Token::MoveVerticalBasic(5); 6]);
+    }
+
+    #[test]
+    fn test_count_anti_patterns() {
+        let tokens = vec![
+            Token::AntiPattern(AntiPattern::ArrowKey),
+            Token::MoveVerticalBasic(1),
+            Token::AntiPattern(AntiPattern::ArrowKey),
+            Token::AntiPattern(AntiPattern::Mouse),
+        ];
+        let penalties = count_anti_patterns(&tokens);
+        assert_eq!(penalties.get("ArrowKey"), Some(&2));
+        assert_eq!(penalties.get("Mouse"), Some(&1));
+        assert_eq!(penalties.len(), 2);
+    }
+}
@@ -0,0 +1,388 @@
+//! Leader key echoes
+//!
+//! `vim.on_key` replays the expansion of `<Leader>` and `<LocalLeader>`
+//! mappings, so a binding arrives twice: the keys as typed, then the leader in
+//! `keytrans` notation followed by the same binding. With a `<Space>` leader,
+//! pressing `<Space>sf` yields `|space|sf<Space>sf`; with `,` it yields `,sf,sf`.
+//! The whole sequence is cut from the batch before lexing.
+//!
+//! Keys alone cannot tell an echo from typed commands (`,dd,dd` with `,`
+//! repeating a find), so only short bindings of plain keys are looked for, and
+//! the binding and its echo must be typed in one mode: while Neovim waits for
+//! the rest of a mapping, no key changes the mode.
+
+use std::ops::Range;
+use std::sync::{LazyLock, Mutex};
+
+use crate::mode::{Mode, ModeRuns};
+
+// Leaders in effect, `<Space>` until the Lua side sends `mapleader` and
+// `maplocalleader`
+static LEADERS: LazyLock<Mutex<Vec<Leader>>> = LazyLock::new(|| Mutex::new(vec![Leader::new(" ")]));
+
+// Most keys after the leader an echo is looked for with
+const MAX_BINDING_KEYS: usize = 8;
+
+// `keytrans` notations of a typed `<`, `|` and `\`, plain keys despite the brackets
+const LITERAL_KEYS: &[&str] = &["<lt>", "<Bar>", "<Bslash>"];
+
+/// A leader key sequence in the two forms it reaches the backend in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leader {
+    // As `keys.lua` sends the typed keys (`|space|`, `,`, `\`)
+    typed: String,
+    // As the echo writes it in front of the replayed binding (`<Space>`, `,`)
+    echo: String,
+}
+
+impl Leader {
+    /// Derive both forms from the raw `mapleader` value (`" "`, `","`, `",,"`)
+    pub fn new(raw: &str) -> Self {
+        Self {
+            typed: raw.chars().map(typed_form).collect(),
+            echo: raw.chars().map(notation).collect(),
+        }
+    }
+
+    /// End of the echo when `input[start..]` is this leader, a binding and
+    /// the echo of both. The binding is grown a key at a time up to the
+    /// echoed leader, giving up at a special key (`|enter|`, `<C-W>`) or after
+    /// `MAX_BINDING_KEYS`.
+    fn echo_end(&self, input: &str, start: usize) -> Option<usize> {
+        let rest = input[start..].strip_prefix(self.typed.as_str())?;
+        let binding_start = start + self.typed.len();
+
+        let mut binding_len = 0;
+        for _ in 0..MAX_BINDING_KEYS {
+            binding_len += plain_key_len(&rest[binding_len..])?;
+            let binding = &rest[..binding_len];
+            if rest[binding_len..]
+                .strip_prefix(self.echo.as_str())
+                .is_some_and(|after_echo| after_echo.starts_with(binding))
+            {
+                return Some(binding_start + 2 * binding_len + self.echo.len());
+            }
+        }
+        None
+    }
+}
+
+/// Replace the leaders echoes are found for. Empty and repeated leaders are
+/// dropped, `<Space>` is kept when none is left.
+pub fn set(raw_leaders: &[String]) {
+    let mut leaders: Vec<Leader> = Vec::new();
+    for raw in raw_leaders.iter().filter(|raw| !raw.is_empty()) {
+        let leader = Leader::new(raw);
+        if !leaders.contains(&leader) {
+            leaders.push(leader);
+        }
+    }
+    if leaders.is_empty() {
+        leaders.push(Leader::new(" "));
+    }
+    if let Ok(mut current) = LEADERS.lock() {
+        *current = leaders;
+    }
+}
+
+pub fn current() -> Vec<Leader> {
+    LEADERS
+        .lock()
+        .map_or_else(|_| vec![Leader::new(" ")], |leaders| leaders.clone())
+}
+
+/// Byte ranges of every leader echo in `input`, sorted and not overlapping.
+///
+/// Leader mappings are not typed on the command line or in insert mode, so a
+/// leader typed there (`\s\s` in a `:s` pattern with a `\` leader) never
+/// starts an echo. Keys of unknown mode may, and an echo never reaches past
+/// the mode its leader was typed in.
+pub fn echo_ranges(input: &str, leaders: &[Leader], modes: &ModeRuns) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+
+    while let Some(ch) = input[offset..].chars().next() {
+        let mappable = modes
+            .at(offset)
+            .is_none_or(|mode| !mode.is_untracked() && mode != Mode::Cmdline);
+        let run_end = modes
+            .run_end(offset)
+            .map_or(input.len(), |end| end.min(input.len()));
+        let run = &input[..run_end];
+        let echo_end = leaders
+            .iter()
+            .filter(|_| mappable)
+            .find_map(|leader| leader.echo_end(run, offset));
+
+        if let Some(end) = echo_end {
+            ranges.push(offset..end);
+            offset = end;
+        } else {
+            offset += ch.len_utf8();
+        }
+    }
+
+    ranges
+}
+
+/// Length of the key at the head of `keys` if it is a plain key, None for a
+/// special key and at the end of input
+fn plain_key_len(keys: &str) -> Option<usize> {
+    if let Some(literal) = LITERAL_KEYS
+        .iter()
+        .find(|literal| keys.starts_with(**literal))
+    {
+        return Some(literal.len());
+    }
+    match keys.chars().next()? {
+        '|' | '<' => None,
+        ch => Some(ch.len_utf8()),
+    }
+}
+
+/// A raw key the way `keys.lua` sends it
+fn typed_form(ch: char) -> String {
+    match ch {
+        ' ' => "|space|".into(),
+        '\t' => "|tab|".into(),
+        '\r' => "|enter|".into(),
+        '\x1b' => "|escape|".into(),
+        '\x08' => "|backspace|".into(),
+        _ => notation(ch),
+    }
+}
+
+/// A raw key in `keytrans` notation
+fn notation(ch: char) -> String {
+    match ch {
+        ' ' => "<Space>".into(),
+        '<' => "<lt>".into(),
+        '|' => "<Bar>".into(),
+        '\t' => "<Tab>".into(),
+        '\r' => "<CR>".into(),
+        '\n' => "<NL>".into(),
+        '\x1b' => "<Esc>".into(),
+        '\x08' => "<BS>".into(),
+        '\x00'..='\x1f' => format!("<C-{}>", char::from_u32(u32::from(ch) + 0x40).unwrap_or(ch)),
+        _ => ch.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(input: &str, raw_leaders: &[&str]) -> String {
+        let leaders: Vec<Leader> = raw_leaders.iter().map(|raw| Leader::new(raw)).collect();
+        let mut result = input.to_string();
+        for range in echo_ranges(input, &leaders, &ModeRuns::default())
+            .iter()
+            .rev()
+        {
+            result.replace_range(range.clone(), "");
+        }
+        result
+    }
+
+    #[test]
+    fn test_leader_forms() {
+        assert_eq!(
+            Leader::new(" "),
+            Leader {
+                typed: "|space|".into(),
+                echo: "<Space>".into()
+            }
+        );
+        assert_eq!(
+            Leader::new(",,"),
+            Leader {
+                typed: ",,".into(),
+                echo: ",,".into()
+            }
+        );
+        assert_eq!(
+            Leader::new("\\<"),
+            Leader {
+                typed: "\\<lt>".into(),
+                echo: "\\<lt>".into()
+            }
+        );
+        assert_eq!(Leader::new("\x17").echo, "<C-W>");
+    }
+
+    #[test]
+    fn test_comma_leader() {
+        assert_eq!(strip("j,sf,sfk", &[","]), "jk");
+        // A lone `,` repeating a find is left alone
+        assert_eq!(strip("fa,j", &[","]), "fa,j");
+    }
+
+    #[test]
+    fn test_backslash_localleader() {
+        assert_eq!(strip("|space|ff<Space>ffj\\t\\tk", &[" ", "\\"]), "jk");
+    }
+
+    #[test]
+    fn test_multi_key_leader() {
+        assert_eq!(strip(",,a,,aj", &[",,"]), "j");
+        // A single `,` is not the leader
+        assert_eq!(strip(",a,a", &[",,"]), ",a,a");
+    }
+
+    #[test]
+    fn test_long_punctuated_binding() {
+        let input = "|space|g?-=[]./<Space>g?-=[]./j";
+        assert_eq!(strip(input, &[" "]), "j");
+        let input = "|space|g<lt><Bar>x<Space>g<lt><Bar>xj";
+        assert_eq!(strip(input, &[" "]), "j");
+    }
+
+    #[test]
+    fn test_binding_longer_than_cap_is_kept() {
+        let input = "|space|abcdefghi<Space>abcdefghi";
+        assert_eq!(strip(input, &[" "]), input);
+    }
+
+    #[test]
+    fn test_binding_stops_at_special_key() {
+        let input = "ta;x,jjjjjjjjjjj:w|enter|kk,jjjjjjjjjjj:w|enter|kk";
+        assert_eq!(strip(input, &[","]), input);
+        assert_eq!(
+            strip(",j:w|enter|,j:w|enter|", &[","]),
+            ",j:w|enter|,j:w|enter|"
+        );
+    }
+
+    #[test]
+    fn test_no_echo_across_mode_change() {
+        // fa, then `,` repeating the find before each dd, whose second d is
+        // typed in operator-pending mode
+        let input = "fa,dd,dd";
+        let leaders = [Leader::new(",")];
+        let modes = ModeRuns::decode("n:4;no:1;n:2;no:1;");
+        assert!(echo_ranges(input, &leaders, &modes).is_empty());
+        // A binding and its echo typed in one mode are cut
+        let modes = ModeRuns::decode("n:2;n:6;");
+        assert_eq!(echo_ranges(input, &leaders, &modes), vec![2..8]);
+    }
+
+    #[test]
+    fn test_shortest_echo_wins() {
+        // Two comma bindings in a row, each with its own echo
+        assert_eq!(strip(",a,a,b,bj", &[","]), "j");
+        // The first `,` has no echo, the second one does
+        assert_eq!(strip(",a,b,b", &[","]), ",a");
+    }
+
+    #[test]
+    fn test_no_echo_in_command_line() {
+        let input = ":s/\\s\\s/x/|enter|";
+        let modes = ModeRuns::decode("n:1;c:18;");
+        let leaders = [Leader::new("\\")];
+        assert!(echo_ranges(input, &leaders, &modes).is_empty());
+        // Without modes the pattern looks like an echo
+        assert_eq!(
+            echo_ranges(input, &leaders, &ModeRuns::default()).first(),
+            Some(&(3..7))
+        );
+    }
+
+    #[test]
+    fn test_set_drops_empty_and_repeated_leaders() {
+        set(&[",".into(), ",".into(), String::new()]);
+        assert_eq!(current(), vec![Leader::new(",")]);
+        set(&[String::new()]);
+        assert_eq!(current(), vec![Leader::new(" ")]);
+    }
+
+    #[test]
+    fn test_strip_leader_basic() {
+        // <Space>sf echoed as |space|sf<Space>sf → entire sequence removed
+        let input = "|space|sf<Space>sf";
+        assert_eq!(strip(input, &[" "]), "");
+    }
+
+    #[test]
+    fn test_strip_leader_single_char() {
+        // Single-char leader binding: <Space>e → |space|e<Space>e
+        let input = "|space|e<Space>e";
+        assert_eq!(strip(input, &[" "]), "");
+    }
+
+    #[test]
+    fn test_strip_leader_longer_binding() {
+        // Longer binding: <Space>abc → |space|abc<Space>abc
+        let input = "|space|abc<Space>abc";
+        assert_eq!(strip(input, &[" "]), "");
+    }
+
+    #[test]
+    fn test_strip_leader_with_surrounding_keys() {
+        // Leader echo embedded within other keystrokes
+        let input = "jjk|space|sf<Space>sfhhl";
+        assert_eq!(strip(input, &[" "]), "jjkhhl");
+    }
+
+    #[test]
+    fn test_strip_leader_multiple_echoes() {
+        // Two separate leader echoes in one batch
+        let input = "|space|sf<Space>sfjj|space|ab<Space>ab";
+        assert_eq!(strip(input, &[" "]), "jj");
+    }
+
+    #[test]
+    fn test_strip_leader_no_echo() {
+        // |space| without a matching <Space> echo — left untouched
+        let input = "|space|sfjjkk";
+        assert_eq!(strip(input, &[" "]), "|space|sfjjkk");
+    }
+
+    #[test]
+    fn test_strip_leader_no_space_at_all() {
+        // No |space| in input — returned unchanged
+        let input = "jjkkhh";
+        assert_eq!(strip(input, &[" "]), "jjkkhh");
+    }
+
+    #[test]
+    fn test_strip_leader_empty_input() {
+        assert_eq!(strip("", &[" "]), "");
+    }
+
+    #[test]
+    fn test_strip_leader_mismatch_not_stripped() {
+        // <Space> is present but the chars after it don't match — no stripping
+        let input = "|space|sf<Space>xy";
+        assert_eq!(strip(input, &[" "]), "|space|sf<Space>xy");
+    }
+
+    #[test]
+    fn test_strip_leader_later_space_not_consumed_by_earlier_mismatch() {
+        // Regression: multiple |space| where the first has no <Space> echo but
+        // a later one does. The mismatch branch must not consume the <Space>
+        // so the correct |space| can still match against it.
+        let input = ":w|enter|<C-D><C-D><C-U><C-U>yyyy110yyGG|space|ss|space|s|escape|x|space|sw<Space>sw|escape|:q|escape|:Vimsacpe|backspace||backspace||backspace||backspace|cape|space|flush|enter|";
+        let expected = ":w|enter|<C-D><C-D><C-U><C-U>yyyy110yyGG|space|ss|space|s|escape|x|escape|:q|escape|:Vimsacpe|backspace||backspace||backspace||backspace|cape|space|flush|enter|";
+        assert_eq!(strip(input, &[" "]), expected);
+    }
+
+    #[test]
+    fn test_strip_leader_preserves_pipe_space_in_commands() {
+        // |space| inside a command (e.g., :Vimscape|space|toggle) — no <Space> echo,
+        // so it passes through untouched
+        let input = ":Vimscape|space|toggle|enter|";
+        assert_eq!(strip(input, &[" "]), ":Vimscape|space|toggle|enter|");
+    }
+
+    #[test]
+    fn test_leader_echo_ranges_shift_modes() {
+        // The modes of an echo are cut together with its keys
+        let input = "j|space|sf<Space>sf:w|enter||space|a<Space>a";
+        let ranges = echo_ranges(input, &[Leader::new(" ")], &ModeRuns::default());
+        assert_eq!(ranges, vec![1..19, 28..44]);
+
+        let mut modes = ModeRuns::decode("n:19;c:9;n:16;");
+        modes.remove_ranges(&ranges);
+        assert_eq!(modes, ModeRuns::decode("n:1;c:9;"));
+    }
+}
@@ -8,40 +8,130 @@
 //! The lexer operates using the following states:
 //! - `State::None` - Initial state, awaiting first character of a command
 //! - `State::AccumulatingCount` - Parsing numeric prefix (e.g., "123" in "123j")
-//! - `State::OperatorPending` - Awaiting motion after operator (d, y, c)
-//! - `State::CaseOperatorPending` - Awaiting motion after case operator (g~, gu, gU)
+//! - `State::OperatorPending` - Awaiting motion after operator (d, y, c, >, <, =, !, gq, gw, g?,
+//!   g~, gu, gU, zf)
 //! - `State::CommandMode` - Parsing Ex command after ':'
 //! - `State::SearchMode` - Parsing search pattern after '/' or '?'
 //! - `State::ReplaceMode` - Parsing replacement character after 'r'
+//! - `State::VisualMode` - Tracking a selection after 'v', 'V' or '<C-V>' until an
+//!   operator or `|escape|` ends it
 //!
-//! # Intentionally Unhandled Commands
+//! Insert mode itself is never seen: the Lua side sends keys typed while in insert
+//! mode, including the `<Esc>` that leaves it, as timestamps without any bytes. The
+//! commands that enter insert mode (`i`, `a`, `o`, ...) are complete tokens on their
+//! own, so the next key in the stream is always a fresh normal mode command.
 //!
-//! The following command categories are intentionally returned as `Token::Unhandled`:
+//! # Modes
 //!
-//! - **Insert mode commands** (`o`, `O`, `i`, `I`, `a`, `A`): These enter insert mode,
-//!   which is not tracked by this lexer since the game handles text input separately.
+//! Given the `nvim_get_mode()` mode of each key (`with_modes`), the lexer stops
+//! guessing at mode changes it cannot see. Keys typed in insert, select, terminal
+//! or prompt modes (`y`/`n`/`q` answers to `:s///c`) are skipped, a command line
+//! or selection ends when its keys stop being typed in that mode (`<C-C>`,
+//! mappings), and one opened without a key the lexer knows starts its state.
+//! Keys of unknown mode are lexed by inference alone.
 //!
-//! - **Visual mode commands** (`v`, `V`, `<C-V>`): Visual mode selection is a future
-//!   consideration per the project specification.
+//! # Key Encoding
 //!
-//! - **Macro commands** (`q`, `@`): Macro recording and playback is a future
-//!   consideration per the project specification.
+//! Printable keys arrive as typed. Special keys arrive pipe-delimited from the Lua
+//! side (`|enter|`, `|up|`) or in `keytrans` notation (`<C-W>`, `<M-j>`, `<S-Tab>`),
+//! and both parse into the same `SpecialKey`. A typed `|` or `<` arrives as `<Bar>`
+//! or `<lt>` (`keytrans` can also write `\` as `<Bslash>`), which `KeyStream` hands
+//! over as the plain character flagged as literal, so it never opens a key name.
+//! A special key is read as one key wherever a command takes an argument, and
+//! `|escape|` or `<C-C>` there cancels the command without a token (`d|escape|`).
 //!
-//! - **Window split commands** (`:sp`, `:vs`): Window management beyond basic
-//!   navigation is handled elsewhere.
-
-use std::{iter::Peekable, str::Chars};
-
-use crate::token::Token;
-
-#[derive(Debug, Clone, Copy)]
-enum Operator {
-    Delete, // d
-    Yank,   // y
-    Change, // c
+//! # Unhandled Keys
+//!
+//! Only keys the lexer cannot classify are returned as `Token::Unhandled`:
+//!
+//! - **Unknown keys**: a key that starts no command (`Q`, `K`), or an argument a
+//!   command does not take (`gQ`).
+//! - **Unknown special keys**: key names no command maps to (`<F5>`, `<M-j>`).
+//!
+//! Ex commands missing from `EX_COMMANDS` (`:Vimscape`, plugin commands) are not
+//! unhandled, they still earn a flat `Token::Command`.
+
+use std::{iter::Peekable, str::CharIndices};
+
+use crate::echo::{self, Echo};
+use crate::mode::{Mode, ModeRuns};
+use crate::token::{
+    AntiPattern, BracketKind, ExAddress, ExAddressKind, ExCommand, ExRange, Motion, Operator,
+    OperatorAction, SpannedToken, TextObject, Token, WindowCommand,
+};
+
+// Ex command names with the length of their shortest abbreviation, as listed
+// in `:help ex-cmd-index`. Checked in order, so `s` is substitute and `sp` is split.
+const EX_COMMANDS: &[(&str, usize, ExCommand)] = &[
+    ("substitute", 1, ExCommand::Substitute),
+    ("global", 1, ExCommand::Global),
+    ("vglobal", 1, ExCommand::Global),
+    ("normal", 4, ExCommand::Normal),
+    ("sort", 3, ExCommand::Sort),
+    ("delete", 1, ExCommand::Delete),
+    ("yank", 1, ExCommand::Yank),
+    ("move", 1, ExCommand::Move),
+    ("copy", 2, ExCommand::Copy),
+    ("t", 1, ExCommand::Copy),
+    ("join", 1, ExCommand::Join),
+    ("edit", 1, ExCommand::Edit),
+    ("quit", 1, ExCommand::Quit),
+    ("qall", 2, ExCommand::Quit),
+    ("quitall", 5, ExCommand::Quit),
+    ("wq", 2, ExCommand::WriteQuit),
+    ("wqall", 3, ExCommand::WriteQuit),
+    ("xit", 1, ExCommand::WriteQuit),
+    ("xall", 2, ExCommand::WriteQuit),
+    ("exit", 3, ExCommand::WriteQuit),
+    ("split", 2, ExCommand::Split),
+    ("vsplit", 2, ExCommand::Split),
+    ("new", 3, ExCommand::Split),
+    ("vnew", 3, ExCommand::Split),
+    ("bnext", 2, ExCommand::Buffer),
+    ("bNext", 2, ExCommand::Buffer),
+    ("bprevious", 2, ExCommand::Buffer),
+    ("bdelete", 2, ExCommand::Buffer),
+    ("bfirst", 2, ExCommand::Buffer),
+    ("blast", 2, ExCommand::Buffer),
+    ("badd", 3, ExCommand::Buffer),
+    ("bwipeout", 2, ExCommand::Buffer),
+    ("buffer", 1, ExCommand::Buffer),
+    ("cnext", 2, ExCommand::Quickfix),
+    ("cNext", 2, ExCommand::Quickfix),
+    ("cprevious", 2, ExCommand::Quickfix),
+    ("copen", 4, ExCommand::Quickfix),
+    ("cclose", 3, ExCommand::Quickfix),
+    ("make", 3, ExCommand::Make),
+    ("ls", 2, ExCommand::Buffer),
+    ("buffers", 7, ExCommand::Buffer),
+    ("tabnew", 6, ExCommand::Tab),
+    ("tabedit", 4, ExCommand::Tab),
+    ("tabnext", 4, ExCommand::Tab),
+    ("tabNext", 4, ExCommand::Tab),
+    ("tabprevious", 4, ExCommand::Tab),
+    ("tabclose", 4, ExCommand::Tab),
+    ("tabonly", 4, ExCommand::Tab),
+    ("tabfirst", 6, ExCommand::Tab),
+    ("tablast", 4, ExCommand::Tab),
+    ("tabmove", 4, ExCommand::Tab),
+    ("tabs", 4, ExCommand::Tab),
+    ("args", 2, ExCommand::Args),
+    ("argadd", 4, ExCommand::Args),
+    ("next", 1, ExCommand::Args),
+    ("Next", 1, ExCommand::Args),
+    ("previous", 4, ExCommand::Args),
+    ("first", 3, ExCommand::Args),
+    ("last", 2, ExCommand::Args),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VisualKind {
+    Char,  // v
+    Line,  // V
+    Block, // <C-V>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum State {
     None,
     AccumulatingCount(u32),
@@ -49,57 +139,472 @@ enum State {
     CommandMode { content: String },
     SearchMode { content: String },
     ReplaceMode { content: String },
-    CaseOperatorPending { operator: String, count: u32 }, // "g~", "gu", "gU"
+    VisualMode { kind: VisualKind },
 }
 
 /// Result of handling a simple command character
 enum CommandResult {
     /// Return this token immediately
     Token(Token),
-    /// Need to consume next char, if present return first token, else second
-    ConsumeNextOptional(Token, Token),
+    /// Takes an argument key (f{char}, r{char}, m{a-z}), return the token once
+    /// it is read
+    ConsumeNextOptional(Token),
     /// Not a simple command - needs special handling
     NotSimple,
 }
 
+// Longest unfinished command carried into the next batch. Anything longer is
+// lexed as-is, so a stray `:` can't swallow keys forever.
+const MAX_PENDING_LEN: usize = 256;
+
+// What Neovim puts on the command line when `:` is pressed in visual mode
+const VISUAL_RANGE: &str = "'<,'>";
+
+// Keys `keytrans` writes in `<>` notation because the raw character would
+// read as the start of a `|key|` or `<key>` name. The stream hands them to the
+// lexer as the plain character, flagged as literal.
+const LITERAL_KEYS: &[(&str, char)] = &[("<Bar>", '|'), ("<lt>", '<'), ("<Bslash>", '\\')];
+
+// Names accepted inside `<>` notation, with the pipe-delimited name of the
+// keys the Lua side can also send as `|name|`
+const NOTATION_KEYS: &[(&str, Option<&str>)] = &[
+    ("CR", Some("enter")),
+    ("Enter", Some("enter")),
+    ("Return", Some("enter")),
+    ("Esc", Some("escape")),
+    ("Tab", Some("tab")),
+    ("BS", Some("backspace")),
+    ("Space", Some("space")),
+    ("Up", Some("up")),
+    ("Down", Some("down")),
+    ("Left", Some("left")),
+    ("Right", Some("right")),
+    ("Home", Some("home")),
+    ("End", Some("end")),
+    ("PageUp", Some("pageup")),
+    ("PageDown", Some("pagedown")),
+    ("Insert", Some("insert")),
+    ("Del", Some("delete")),
+    ("Bar", None),
+    ("lt", None),
+    ("Bslash", None),
+    ("Nul", None),
+    ("NL", None),
+    ("Help", None),
+    ("Undo", None),
+];
+
+// Pipe-delimited key names sent by the Lua side
+const PIPE_KEYS: &[&str] = &[
+    "enter",
+    "tab",
+    "backspace",
+    "space",
+    "escape",
+    "up",
+    "down",
+    "left",
+    "right",
+    "home",
+    "end",
+    "pageup",
+    "pagedown",
+    "insert",
+    "delete",
+    "mouse",
+];
+
+/// A special key in either encoding, `|enter|` or `<CR>`
+#[derive(Debug, Clone, PartialEq)]
+enum SpecialKey {
+    /// A key with a pipe-delimited name (`|enter|`, `<CR>`, `<Up>`)
+    Named(&'static str),
+    /// `<C-x>` without other modifiers, the key upper-cased
+    Ctrl(char),
+    /// Any other notation (`<M-j>`, `<S-Tab>`, `<C-S-x>`, `<F5>`), as written
+    Other(String),
+}
+
+impl SpecialKey {
+    /// The key as the lexer receives it, for `Token::Unhandled`
+    fn notation(&self) -> String {
+        match self {
+            SpecialKey::Named(name) => format!("|{name}|"),
+            SpecialKey::Ctrl(ch) => format!("<C-{ch}>"),
+            SpecialKey::Other(notation) => notation.clone(),
+        }
+    }
+
+    /// The character the key stands for as the argument of `r`, `f` or `t`
+    /// (`r<CR>` splits the line, `f<Tab>` finds a tab)
+    fn typed_char(&self) -> Option<char> {
+        match self {
+            SpecialKey::Named("enter") => Some('\r'),
+            SpecialKey::Named("tab") => Some('\t'),
+            SpecialKey::Named("space") => Some(' '),
+            _ => None,
+        }
+    }
+}
+
+/// The key a pending command reads as its argument (the motion after `d`,
+/// the register after `"`), with a special key taken as one key
+enum ArgKey {
+    Char(char),
+    /// `|escape|` or `<C-C>`, which abandon the pending command
+    Cancel,
+    Special(SpecialKey),
+}
+
+/// Character stream over the raw keys that knows its byte offset and whether
+/// lexing ever looked past the end of the input.
+#[derive(Clone)]
+struct KeyStream<'a> {
+    rest: &'a str,
+    head: Option<char>,
+    // Bytes of `rest` the head char was decoded from (5 for `<Bar>`)
+    head_len: usize,
+    head_literal: bool,
+    last_literal: bool,
+    hit_end: bool,
+}
+
+impl<'a> KeyStream<'a> {
+    fn new(input: &'a str) -> Self {
+        let mut stream = Self {
+            rest: input,
+            head: None,
+            head_len: 0,
+            head_literal: false,
+            last_literal: false,
+            hit_end: false,
+        };
+        stream.decode_head();
+        stream
+    }
+
+    fn decode_head(&mut self) {
+        let literal = LITERAL_KEYS.iter().find(|(notation, _)| {
+            self.rest
+                .get(..notation.len())
+                .is_some_and(|head| head.eq_ignore_ascii_case(notation))
+        });
+        if let Some((notation, ch)) = literal {
+            self.head = Some(*ch);
+            self.head_len = notation.len();
+            self.head_literal = true;
+        } else {
+            self.head = self.rest.chars().next();
+            self.head_len = self.head.map_or(0, char::len_utf8);
+            self.head_literal = false;
+        }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        if self.head.is_none() {
+            self.hit_end = true;
+        }
+        self.head.as_ref()
+    }
+}
+
+impl Iterator for KeyStream<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let Some(ch) = self.head else {
+            self.hit_end = true;
+            return None;
+        };
+        self.rest = &self.rest[self.head_len..];
+        self.last_literal = self.head_literal;
+        self.decode_head();
+        Some(ch)
+    }
+}
+
+/// Lexer state carried from one batch to the next: the keys of a command the
+/// batch cut off (`d` waiting for its motion, `:%s/a/b` waiting for `|enter|`),
+/// the state the lexer was in before them, and whether a macro is recording.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    state: State,
+    recording_macro: bool,
+    pending: String,
+    pending_modes: ModeRuns,
+}
+
+impl Default for Checkpoint {
+    fn default() -> Self {
+        Self {
+            state: State::None,
+            recording_macro: false,
+            pending: String::new(),
+            pending_modes: ModeRuns::default(),
+        }
+    }
+}
+
+impl Checkpoint {
+    /// Raw keys to put in front of the next batch
+    pub fn pending(&self) -> &str {
+        &self.pending
+    }
+
+    /// Modes of the pending keys
+    pub fn pending_modes(&self) -> &ModeRuns {
+        &self.pending_modes
+    }
+
+    /// Replace the guessed macro recording state with the one Neovim reported
+    /// for the start of the next batch. The pending keys are an unfinished
+    /// command, so they cannot have started or stopped a recording.
+    pub fn set_recording_macro(&mut self, recording: bool) {
+        self.recording_macro = recording;
+    }
+}
+
 pub struct Lexer<'a> {
-    input: Peekable<Chars<'a>>,
+    source: &'a str,
+    input: KeyStream<'a>,
+    modes: ModeRuns,
     state: State,
     accumulated_string: String,
+    // A `q{reg}` was seen, so the next bare `q` stops the recording
+    recording_macro: bool,
+    // Stop at a command cut off by the end of input instead of emitting it
+    defer_incomplete: bool,
+    deferred: Option<Checkpoint>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
         Self {
-            input: input.chars().peekable(),
+            source: input,
+            input: KeyStream::new(input),
+            modes: ModeRuns::default(),
             state: State::None,
             accumulated_string: String::new(),
+            recording_macro: false,
+            defer_incomplete: false,
+            deferred: None,
         }
     }
 
-    /// Try to parse a control sequence like `<C-X>`.
-    /// Returns Some(char) with the control character (e.g., 'U', 'D', 'F', etc.) if valid.
-    /// Returns None if not a valid control sequence.
-    /// The caller has already consumed the initial '<'.
-    fn try_parse_control_sequence(&mut self) -> Option<char> {
-        if self.input.peek() != Some(&'C') {
+    /// Pick up where the previous batch stopped. `input` must start with
+    /// `checkpoint.pending()`. With `defer_incomplete`, a command cut off at the
+    /// end of `input` is not emitted but kept for `checkpoint()`.
+    pub fn resume(input: &'a str, checkpoint: Checkpoint, defer_incomplete: bool) -> Self {
+        Self {
+            state: checkpoint.state,
+            recording_macro: checkpoint.recording_macro,
+            defer_incomplete,
+            ..Self::new(input)
+        }
+    }
+
+    /// Lex using the mode each key of the input was typed in
+    pub fn with_modes(mut self, modes: ModeRuns) -> Self {
+        self.modes = modes;
+        self
+    }
+
+    /// State to resume the next batch from, once `next_token` returned None.
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.deferred.clone().unwrap_or_else(|| Checkpoint {
+            state: self.state.clone(),
+            recording_macro: self.recording_macro,
+            pending: String::new(),
+            pending_modes: ModeRuns::default(),
+        })
+    }
+
+    fn offset(&self) -> usize {
+        self.source.len() - self.input.rest.len()
+    }
+
+    /// Mode the next key was typed in, None at the end or when unknown
+    fn head_mode(&self) -> Option<Mode> {
+        self.input.head.and_then(|_| self.modes.at(self.offset()))
+    }
+
+    /// Skip keys typed where nothing is a command, and with nothing in
+    /// progress, enter the state of a command line or selection that was
+    /// opened without a key the lexer knows (a mapping, the mouse, `gv`).
+    /// Keys typed in operator-pending mode with no operator seen are dropped.
+    fn sync_with_mode(&mut self) {
+        while let Some(mode) = self.head_mode() {
+            let orphaned = matches!(self.state, State::None) && mode == Mode::OperatorPending;
+            if !mode.is_untracked() && !orphaned {
+                break;
+            }
+            self.input.next();
+        }
+
+        if !matches!(self.state, State::None) {
+            return;
+        }
+        self.state = match self.head_mode() {
+            Some(Mode::Cmdline) => State::CommandMode {
+                content: String::new(),
+            },
+            Some(Mode::Replace) => State::ReplaceMode {
+                content: String::new(),
+            },
+            Some(Mode::Visual) => State::VisualMode {
+                kind: VisualKind::Char,
+            },
+            Some(Mode::VisualLine) => State::VisualMode {
+                kind: VisualKind::Line,
+            },
+            Some(Mode::VisualBlock) => State::VisualMode {
+                kind: VisualKind::Block,
+            },
+            _ => return,
+        };
+    }
+
+    #[cfg(test)]
+    pub fn next_token(&mut self) -> Option<Token> {
+        self.next_token_with_span().map(|spanned| spanned.token)
+    }
+
+    /// Like `next_token`, but also returns the byte range of the input the
+    /// token was lexed from.
+    pub fn next_token_with_span(&mut self) -> Option<SpannedToken> {
+        if self.deferred.is_some() {
+            return None;
+        }
+
+        let (start, start_state, start_recording, token) = loop {
+            self.sync_with_mode();
+            let start_state = self.state.clone();
+            let start_recording = self.recording_macro;
+            let start = self.offset();
+            self.input.hit_end = false;
+
+            let token = self.lex_token();
+            // A command cancelled with |escape| or <C-C> emits nothing, lex
+            // what follows it
+            if token.is_some() || self.offset() == start {
+                break (start, start_state, start_recording, token);
+            }
+        };
+
+        // Commands `vim.on_key` reports twice as a whole (zz arrives as zzzz)
+        if let Some(token) = &token
+            && echo::echoes(token, &self.source[start..self.offset()], Echo::Command)
+        {
+            self.skip_echo(start);
+        }
+
+        // A token that needed keys past the end may be the start of a longer
+        // command, leave it for the next batch
+        if self.defer_incomplete
+            && self.input.hit_end
+            && self.source.len() - start <= MAX_PENDING_LEN
+        {
+            self.deferred = Some(Checkpoint {
+                state: start_state,
+                recording_macro: start_recording,
+                pending: self.source[start..].to_string(),
+                pending_modes: self.modes.split_off(start),
+            });
             return None;
         }
-        self.input.next();
 
-        if self.input.peek() != Some(&'-') {
+        token.map(|token| SpannedToken {
+            token,
+            span: start..self.offset(),
+        })
+    }
+
+    /// Try to parse a key in `<>` notation (`<C-X>`, `<CR>`, `<M-j>`, `<S-Tab>`).
+    /// Returns None if no notation starts here, leaving the input untouched
+    /// so a plain `<` can be lexed as the unindent operator. A `<lt>` never
+    /// opens a notation.
+    /// The caller has already consumed the initial '<'.
+    fn try_parse_key_notation(&mut self) -> Option<SpecialKey> {
+        if self.input.last_literal {
             return None;
         }
-        self.input.next();
+        let mut probe = self.input.clone();
+        let key = Self::parse_key_notation(&mut probe);
+        if key.is_some() {
+            self.input = probe;
+        } else {
+            // `<C-` cut off by the end of the batch may still become a key
+            self.input.hit_end |= probe.hit_end;
+        }
+        key
+    }
+
+    /// Parse the modifiers and key name of a `<>` notation up to its `>`.
+    /// A single char needs a modifier (`<C-x>`), longer names must be known keys.
+    fn parse_key_notation(iter: &mut KeyStream) -> Option<SpecialKey> {
+        let mut body = String::new();
+        loop {
+            match iter.next()? {
+                '>' if !body.is_empty() => break,
+                '<' => return None,
+                ch => {
+                    body.push(ch);
+                    if body.len() > 16 {
+                        return None;
+                    }
+                }
+            }
+        }
 
-        let ctrl_char = self.input.next()?;
+        let mut ctrl = false;
+        let mut other_modifier = false;
+        let mut key = body.as_str();
+        while let [modifier, b'-', _, ..] = key.as_bytes() {
+            match modifier.to_ascii_uppercase() {
+                b'C' => ctrl = true,
+                b'S' | b'M' | b'A' | b'D' | b'T' => other_modifier = true,
+                _ => break,
+            }
+            key = &key[2..];
+        }
 
-        if self.input.peek() != Some(&'>') {
+        let named = NOTATION_KEYS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key));
+        let is_char = key.chars().count() == 1;
+        let is_function_key = key.len() > 1
+            && key.starts_with(['F', 'f'])
+            && key[1..].bytes().all(|b| b.is_ascii_digit());
+        if !is_char && !is_function_key && named.is_none() {
             return None;
         }
-        self.input.next();
 
-        Some(ctrl_char)
+        match (ctrl, other_modifier) {
+            (false, false) => match named {
+                Some((_, Some(pipe_name))) => Some(SpecialKey::Named(pipe_name)),
+                // A bare char in brackets is not a key (`<a>`)
+                _ if is_char => None,
+                _ => Some(SpecialKey::Other(format!("<{body}>"))),
+            },
+            (true, false) if is_char => key
+                .chars()
+                .next()
+                .map(|ch| SpecialKey::Ctrl(ch.to_ascii_uppercase())),
+            _ => Some(SpecialKey::Other(format!("<{body}>"))),
+        }
+    }
+
+    /// Token for a special key pressed in normal mode (`|up|`, `<Up>`, `|tab|`)
+    fn special_key_token(key: &str) -> Token {
+        match key {
+            "up" | "down" | "left" | "right" => Token::AntiPattern(AntiPattern::ArrowKey),
+            "pageup" | "pagedown" => Token::AntiPattern(AntiPattern::PageKey),
+            "home" | "end" => Token::AntiPattern(AntiPattern::HomeEndKey),
+            "mouse" => Token::AntiPattern(AntiPattern::Mouse),
+            // <C-I> arrives as |tab|, the keys are the same to a terminal
+            "tab" => Token::JumpList,
+            _ => Token::Unhandled(format!("|{key}|")),
+        }
     }
 
     /// Handle a control sequence and return the appropriate token.
@@ -110,110 +615,366 @@ impl<'a> Lexer<'a> {
             'F' | 'B' => Token::JumpToVertical,
             'E' | 'Y' => Token::CameraMovement,
             'R' => Token::UndoRedo,
-            'H' | 'J' | 'K' | 'L' => Token::WindowManagement,
-            'W' => {
-                let _ = self.input.next();
-                Token::WindowManagement
+            'O' | 'I' => Token::JumpList,
+            '^' | '6' => Token::BufferSwitch,
+            'A' | 'X' => Token::Increment(i32::try_from(count).unwrap()),
+            'H' | 'J' | 'K' | 'L' => Token::WindowManagement(WindowCommand::Focus),
+            'V' => {
+                self.state = State::VisualMode {
+                    kind: VisualKind::Block,
+                };
+                self.lex_token()
+                    .unwrap_or_else(|| Token::Unhandled("<C-V>".into()))
             }
+            'W' => self.handle_window_command(count),
             _ => Token::Unhandled(format!("<C-{ctrl_char}>")),
         }
     }
 
+    /// Handle the window command after `<C-W>`. The count can come before `<C-W>`
+    /// or right after it (`5<C-W>+`, `<C-W>5+`), control forms (`<C-W><C-V>`)
+    /// act like the plain key.
+    fn handle_window_command(&mut self, count: u32) -> Token {
+        let mut count = count;
+        if self.input.peek().is_some_and(|ch| ('1'..='9').contains(ch)) {
+            let mut inner = 0u32;
+            while let Some(digit) = self.input.peek().and_then(|ch| ch.to_digit(10)) {
+                self.input.next();
+                inner = inner.saturating_mul(10).saturating_add(digit).min(999);
+            }
+            count = count.saturating_mul(inner).min(999);
+        }
+
+        // <C-W>|escape| cancels the command
+        if let Some((SpecialKey::Named(_), _)) = self.peek_special_key() {
+            return Token::Unhandled("<C-W>".into());
+        }
+
+        let Some(key) = self.input.next() else {
+            return Token::Unhandled("<C-W>".into());
+        };
+        let key = if key == '<' {
+            match self.try_parse_key_notation() {
+                Some(SpecialKey::Ctrl(ctrl_char)) => ctrl_char.to_ascii_lowercase(),
+                Some(SpecialKey::Other(notation)) => {
+                    return Token::Unhandled(format!("<C-W>{notation}"));
+                }
+                Some(SpecialKey::Named(_)) | None => '<',
+            }
+        } else {
+            key
+        };
+
+        let command = match key {
+            'h' | 'j' | 'k' | 'l' | 'w' | 'W' | 'p' | 't' | 'b' => WindowCommand::Focus,
+            's' | 'S' | 'n' => WindowCommand::Split,
+            'v' => WindowCommand::VSplit,
+            'q' | 'c' => WindowCommand::Close,
+            'o' => WindowCommand::Only,
+            '+' | '-' | '<' | '>' | '_' | '|' => {
+                WindowCommand::Resize(i32::try_from(count).unwrap())
+            }
+            '=' => WindowCommand::Equalize,
+            'r' | 'R' | 'x' => WindowCommand::Rotate,
+            'H' | 'J' | 'K' | 'L' | 'T' => WindowCommand::Move,
+            _ => return Token::Unhandled(format!("<C-W>{key}")),
+        };
+        Token::WindowManagement(command)
+    }
+
     /// Try to parse a pipe-delimited special key like `|enter|`, `|tab|`, etc.
-    /// Returns `Some(key_name)` if valid (e.g., "enter", "tab", "`backspace`", "space").
-    /// Returns None if not a valid pipe sequence.
+    /// Returns `Some(key_name)` if valid (e.g., "enter", "tab", "`backspace`", "up").
+    /// Returns None if not a valid pipe sequence, leaving the input untouched.
+    /// A `<Bar>` never opens a key name.
     /// The caller has already consumed the initial '|'.
-    fn try_parse_pipe_delimited(&mut self) -> Option<String> {
+    fn try_parse_pipe_delimited(&mut self) -> Option<&'static str> {
+        if self.input.last_literal {
+            return None;
+        }
+        let mut probe = self.input.clone();
+        let key_name = Self::parse_pipe_key(&mut probe)?;
+        self.input = probe;
+        Some(key_name)
+    }
+
+    /// Check whether the next character opens a pipe-delimited special key,
+    /// as opposed to a literal `|` (the column motion).
+    fn at_pipe_key(&self) -> bool {
+        let mut probe = self.input.clone();
+        probe.next() == Some('|')
+            && !probe.last_literal
+            && Self::parse_pipe_key(&mut probe).is_some()
+    }
+
+    fn parse_pipe_key(iter: &mut KeyStream) -> Option<&'static str> {
         let mut key_name = String::new();
 
         loop {
-            match self.input.peek() {
-                Some(&'|') => {
-                    self.input.next(); // consume closing '|'
+            match iter.next()? {
+                '|' if !iter.last_literal => {
                     // Validate known pipe-delimited keys
-                    match key_name.as_str() {
-                        "enter" | "tab" | "backspace" | "space" | "escape" => {
-                            return Some(key_name);
-                        }
-                        _ => return None, // Unknown pipe-delimited key
-                    }
+                    return PIPE_KEYS.iter().find(|name| **name == key_name).copied();
                 }
-                Some(&ch) => {
-                    self.input.next();
+                ch => {
                     key_name.push(ch);
                     if key_name.len() > 10 {
                         return None;
                     }
                 }
-                None => return None, // End of input without closing '|'
             }
         }
     }
 
+    /// Look for a special key in either encoding (`|enter|`, `<CR>`) at the
+    /// head of the input. Returns the key and the stream positioned after it.
+    fn peek_special_key(&self) -> Option<(SpecialKey, KeyStream<'a>)> {
+        let mut probe = self.input.clone();
+        let key = match probe.next()? {
+            _ if probe.last_literal => return None,
+            '|' => SpecialKey::Named(Self::parse_pipe_key(&mut probe)?),
+            '<' => Self::parse_key_notation(&mut probe)?,
+            _ => return None,
+        };
+        Some((key, probe))
+    }
+
     /// Check if we've reached a terminator for command/search mode.
     /// Returns Some(true) for |enter| (completed), Some(false) for |escape| (cancelled).
     /// Returns None if current position is not a terminator.
     fn check_command_terminator(&mut self) -> Option<bool> {
-        match self.input.peek() {
-            Some(&'|') => {
-                let mut peek_iter = self.input.clone();
-                peek_iter.next();
+        let (key, rest) = self.peek_special_key()?;
+        let completed = match key {
+            SpecialKey::Named("enter") => true,
+            SpecialKey::Named("escape") => false,
+            _ => return None,
+        };
+        self.input = rest;
+        Some(completed)
+    }
+
+    /// Look for the argument key of a pending command at the head of the
+    /// input. Returns the key and the stream positioned after it.
+    fn peek_arg_key(&self) -> Option<(ArgKey, KeyStream<'a>)> {
+        if let Some((key, rest)) = self.peek_special_key() {
+            let arg = match key {
+                SpecialKey::Named("escape") | SpecialKey::Ctrl('C') => ArgKey::Cancel,
+                key => ArgKey::Special(key),
+            };
+            return Some((arg, rest));
+        }
 
-                let mut key_name = String::new();
-                loop {
-                    match peek_iter.next() {
-                        Some('|') => {
-                            if key_name == "enter" {
-                                // Consume |enter| (7 chars: | e n t e r |)
-                                for _ in 0..7 {
-                                    self.input.next();
-                                }
-                                return Some(true);
-                            }
-                            if key_name == "escape" {
-                                // Consume |escape| (8 chars: | e s c a p e |)
-                                for _ in 0..8 {
-                                    self.input.next();
-                                }
-                                return Some(false);
-                            }
-                            return None;
-                        }
-                        Some(ch) => {
-                            key_name.push(ch);
-                            if key_name.len() > 10 {
-                                return None;
-                            }
-                        }
-                        None => return None,
+        let mut rest = self.input.clone();
+        let ch = rest.next()?;
+        // `<C-` or `|esc` cut off by the end of the batch may still become a key
+        if matches!(ch, '<' | '|') && !rest.last_literal {
+            let mut probe = rest.clone();
+            if ch == '<' {
+                Self::parse_key_notation(&mut probe);
+            } else {
+                Self::parse_pipe_key(&mut probe);
+            }
+            rest.hit_end |= probe.hit_end;
+        }
+        Some((ArgKey::Char(ch), rest))
+    }
+
+    /// Read the argument key of a pending command
+    fn next_arg_key(&mut self) -> Option<ArgKey> {
+        let Some((key, rest)) = self.peek_arg_key() else {
+            self.input.hit_end = true;
+            return None;
+        };
+        self.input = rest;
+        Some(key)
+    }
+
+    /// Consume an `|escape|` or `<C-C>` at the head of the input
+    fn take_cancel(&mut self) -> bool {
+        match self.peek_arg_key() {
+            Some((ArgKey::Cancel, rest)) => {
+                self.input = rest;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Read the argument char of the command typed as `keys`. Anything else
+    /// ends the command: None when it was cancelled, `Unhandled` for a special
+    /// key or the end of input.
+    fn next_arg_char(&mut self, keys: &str) -> Result<char, Option<Token>> {
+        match self.next_arg_key() {
+            Some(ArgKey::Char(ch)) => Ok(ch),
+            Some(ArgKey::Cancel) => Err(None),
+            Some(ArgKey::Special(key)) => {
+                Err(Some(Token::Unhandled(format!("{keys}{}", key.notation()))))
+            }
+            None => Err(Some(Token::Unhandled(keys.to_string()))),
+        }
+    }
+
+    /// Read the argument of f/F/t/T, r or m, typed as `keys`. Special keys
+    /// that type a char count as one (`r<CR>`).
+    fn consume_argument(&mut self, keys: &str, token: Token) -> Option<Token> {
+        match self.next_arg_key() {
+            Some(ArgKey::Special(key)) if key.typed_char().is_none() => {
+                Some(Token::Unhandled(format!("{keys}{}", key.notation())))
+            }
+            Some(ArgKey::Char(_) | ArgKey::Special(_)) => Some(token),
+            Some(ArgKey::Cancel) => None,
+            None => Some(Token::Unhandled(keys.to_string())),
+        }
+    }
+
+    /// Parse one Ex address with its offsets (`10`, `.`, `$`, `'a`, `/pat/`, `.+3`, `-2`).
+    /// Returns None, consuming nothing, if no address starts here.
+    fn parse_ex_address(chars: &mut Peekable<CharIndices>) -> Option<ExAddress> {
+        while chars.next_if(|&(_, ch)| ch == ' ').is_some() {}
+
+        let &(_, first) = chars.peek()?;
+        let kind = match first {
+            '0'..='9' => {
+                let mut line: u32 = 0;
+                while let Some((_, digit)) = chars.next_if(|&(_, ch)| ch.is_ascii_digit()) {
+                    line = line
+                        .saturating_mul(10)
+                        .saturating_add(digit.to_digit(10).unwrap_or(0));
+                }
+                ExAddressKind::Line(line)
+            }
+            '.' => {
+                chars.next();
+                ExAddressKind::Current
+            }
+            '$' => {
+                chars.next();
+                ExAddressKind::Last
+            }
+            '\'' => {
+                chars.next();
+                let (_, mark) = chars.next()?;
+                ExAddressKind::Mark(mark)
+            }
+            '/' | '?' => {
+                chars.next();
+                let mut pattern = String::new();
+                while let Some((_, ch)) = chars.next() {
+                    if ch == first {
+                        break;
+                    }
+                    pattern.push(ch);
+                    if ch == '\\'
+                        && let Some((_, escaped)) = chars.next()
+                    {
+                        pattern.push(escaped);
                     }
                 }
+                ExAddressKind::Pattern(pattern)
             }
-            _ => None,
+            // A bare offset counts from the cursor line (`:+3`)
+            '+' | '-' => ExAddressKind::Current,
+            _ => return None,
+        };
+
+        let mut offset: i32 = 0;
+        while let Some((_, sign)) = chars.next_if(|&(_, ch)| ch == '+' || ch == '-') {
+            let mut amount: Option<i32> = None;
+            while let Some((_, digit)) = chars.next_if(|&(_, ch)| ch.is_ascii_digit()) {
+                let value = i32::try_from(digit.to_digit(10).unwrap_or(0)).unwrap_or(0);
+                amount = Some(amount.unwrap_or(0).saturating_mul(10).saturating_add(value));
+            }
+            let amount = amount.unwrap_or(1);
+            offset = if sign == '+' {
+                offset.saturating_add(amount)
+            } else {
+                offset.saturating_sub(amount)
+            };
         }
+
+        Some(ExAddress { kind, offset })
+    }
+
+    /// Split an Ex command line into its range and the command that follows
+    /// (`:'<,'>s/a/b/` is `'<,'>` and `s/a/b/`).
+    fn parse_ex_range(command: &str) -> (Option<ExRange>, &str) {
+        let mut chars = command.char_indices().peekable();
+        while chars.next_if(|&(_, ch)| ch == ' ').is_some() {}
+
+        let range = if chars.next_if(|&(_, ch)| ch == '%').is_some() {
+            Some(ExRange::WholeFile)
+        } else {
+            let first = Self::parse_ex_address(&mut chars);
+            let mut last = None;
+            while chars.next_if(|&(_, ch)| ch == ',' || ch == ';').is_some() {
+                // A missing address next to the separator is the cursor line (`:,5`, `:5,`)
+                last = Some(Self::parse_ex_address(&mut chars).unwrap_or(ExAddress {
+                    kind: ExAddressKind::Current,
+                    offset: 0,
+                }));
+            }
+            match (first, last) {
+                (first, Some(last)) => Some(ExRange::Span(
+                    first.unwrap_or(ExAddress {
+                        kind: ExAddressKind::Current,
+                        offset: 0,
+                    }),
+                    last,
+                )),
+                (Some(first), None) => Some(ExRange::Single(first)),
+                (None, None) => None,
+            }
+        };
+
+        while chars.next_if(|&(_, ch)| ch == ' ').is_some() {}
+        let rest = chars.peek().map_or("", |&(i, _)| &command[i..]);
+        (range, rest)
+    }
+
+    /// Match an Ex command name against its full name and shortest abbreviation,
+    /// so `s`, `su` and `substitute` all resolve to the same command.
+    fn lookup_ex_command(name: &str) -> Option<ExCommand> {
+        EX_COMMANDS
+            .iter()
+            .find(|(full, min_len, _)| name.len() >= *min_len && full.starts_with(name))
+            .map(|(_, _, command)| *command)
     }
 
     /// Classify command content and return appropriate token.
     fn classify_command(content: &str, completed: bool) -> Token {
         let trimmed = content.trim();
+        let (range, command) = Self::parse_ex_range(trimmed);
+
+        // A range on its own jumps to its last line (`:42`, `:$`, `:'a`) once
+        // it is run. Entering the visual range and leaving is not a jump.
+        if command.is_empty() {
+            return if range.is_some() && completed && trimmed != VISUAL_RANGE {
+                Token::JumpToLineNumber(trimmed.to_string())
+            } else {
+                Token::Command(completed)
+            };
+        }
 
-        if !trimmed.is_empty() && trimmed.chars().all(|c| c.is_ascii_digit()) {
-            return Token::JumpToLineNumber(trimmed.to_string());
+        let name_len = command
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(command.len());
+        let name = &command[..name_len];
+
+        if name.is_empty() {
+            return Token::Command(completed);
         }
 
-        if trimmed == "h"
-            || trimmed.starts_with("h ")
-            || trimmed == "help"
-            || trimmed.starts_with("help ")
-        {
+        if "help".starts_with(name) {
             return Token::HelpPage(completed);
         }
 
-        if trimmed == "w" || trimmed.starts_with("w ") || trimmed.starts_with("w!") {
+        if "write".starts_with(name) {
             return Token::SaveFile(completed);
         }
 
-        Token::Command(completed)
+        match Self::lookup_ex_command(name) {
+            Some(ex_command) => Token::ExCommand(ex_command, range, completed),
+            None => Token::Command(completed),
+        }
     }
 
     fn accumulate_digit(&mut self, digit: char) -> u32 {
@@ -246,21 +1007,111 @@ impl<'a> Lexer<'a> {
         count
     }
 
-    /// Maps operator + count to the appropriate token
-    fn operator_to_token(operator: Operator, count: u32) -> Token {
+    /// The operator action a shorthand key stands for (`x` is `dl`, `C` is
+    /// `c$`, `S` is `cc`, and `Y` is `y$` in Neovim)
+    fn operator_alias(key: char, count: u32) -> Token {
+        let (operator, motion) = match key {
+            'x' => (Operator::Delete, Motion::Key('l')),
+            'X' => (Operator::Delete, Motion::Key('h')),
+            'D' => (Operator::Delete, Motion::Key('$')),
+            's' => (Operator::Change, Motion::Key('l')),
+            'S' => (Operator::Change, Motion::Line),
+            'C' => (Operator::Change, Motion::Key('$')),
+            _ => (Operator::Yank, Motion::Key('$')),
+        };
+        Self::operator_token(operator, count, Some(motion), None)
+    }
+
+    /// Builds the token for an operator applied to a motion or text object
+    fn operator_token(
+        operator: Operator,
+        count: u32,
+        motion: Option<Motion>,
+        text_object: Option<TextObject>,
+    ) -> Token {
+        Token::Operator(OperatorAction {
+            operator,
+            motion,
+            text_object,
+            count: i32::try_from(count).unwrap(),
+            register: None,
+        })
+    }
+
+    /// Maps a normal mode key to the operator it starts.
+    /// `<` is left out, it is only an operator when it doesn't open a `<C-x>` sequence.
+    fn key_to_operator(ch: char) -> Option<Operator> {
+        match ch {
+            'd' => Some(Operator::Delete),
+            'y' => Some(Operator::Yank),
+            'c' => Some(Operator::Change),
+            '>' => Some(Operator::Indent),
+            '=' => Some(Operator::Reindent),
+            '!' => Some(Operator::Filter),
+            _ => None,
+        }
+    }
+
+    /// Maps the key after `g` to the operator it starts (gq, gw, g?, g~, gu, gU)
+    fn g_operator(key: char) -> Operator {
+        match key {
+            '?' => Operator::Rot13,
+            '~' => Operator::ToggleCase,
+            'u' => Operator::Lowercase,
+            'U' => Operator::Uppercase,
+            'w' => Operator::FormatKeep,
+            _ => Operator::Format,
+        }
+    }
+
+    /// Maps operator to its key sequence (for error messages)
+    fn operator_to_keys(operator: Operator) -> &'static str {
         match operator {
-            Operator::Delete => Token::DeleteText(i32::try_from(count).unwrap()),
-            Operator::Yank => Token::YankPaste,
-            Operator::Change => Token::TextManipulationAdvanced,
+            Operator::Delete => "d",
+            Operator::Yank => "y",
+            Operator::Change => "c",
+            Operator::Fold => "zf",
+            Operator::Indent => ">",
+            Operator::Unindent => "<",
+            Operator::Reindent => "=",
+            Operator::Format => "gq",
+            Operator::FormatKeep => "gw",
+            Operator::Filter => "!",
+            Operator::Rot13 => "g?",
+            Operator::ToggleCase => "g~",
+            Operator::Lowercase => "gu",
+            Operator::Uppercase => "gU",
         }
     }
 
-    /// Maps operator to its character representation (for error messages)
-    fn operator_to_char(operator: Operator) -> char {
+    /// The motion char that makes a linewise operation (dd, >>, gqq, gww, g??, guu).
+    /// `zf` has no doubled form, `zF` is lexed on its own.
+    fn operator_line_char(operator: Operator) -> Option<char> {
         match operator {
-            Operator::Delete => 'd',
-            Operator::Yank => 'y',
-            Operator::Change => 'c',
+            Operator::Delete => Some('d'),
+            Operator::Yank => Some('y'),
+            Operator::Change => Some('c'),
+            Operator::Fold => None,
+            Operator::Indent => Some('>'),
+            Operator::Unindent => Some('<'),
+            Operator::Reindent => Some('='),
+            Operator::Format => Some('q'),
+            Operator::FormatKeep => Some('w'),
+            Operator::Filter => Some('!'),
+            Operator::Rot13 => Some('?'),
+            Operator::ToggleCase => Some('~'),
+            Operator::Lowercase => Some('u'),
+            Operator::Uppercase => Some('U'),
+        }
+    }
+
+    /// Skip the command line that `!{motion}` opens (`:.,.+2!sort|enter|`).
+    /// The typed filter command is part of the operator, not a separate Ex command.
+    fn skip_filter_command(&mut self) {
+        while self.check_command_terminator().is_none() {
+            if self.input.next().is_none() {
+                return;
+            }
         }
     }
 
@@ -272,58 +1123,80 @@ impl<'a> Lexer<'a> {
         match ch {
             'j' | 'k' => CommandResult::Token(Token::MoveVerticalBasic(count_i32)),
             'h' | 'l' => CommandResult::Token(Token::MoveHorizontalBasic(count_i32)),
-            'w' | 'W' | 'e' | 'E' | 'b' | 'B' => {
+            'w' | 'W' | 'e' | 'E' | 'b' | 'B' | '(' | ')' => {
                 CommandResult::Token(Token::MoveHorizontalChunk(count_i32))
             }
+            '{' | '}' => CommandResult::Token(Token::MoveVerticalChunk(count_i32)),
             'u' | 'U' => CommandResult::Token(Token::UndoRedo),
             '.' => CommandResult::Token(Token::DotRepeat),
             'M' | 'H' | 'L' => CommandResult::Token(Token::JumpToVertical),
-            'p' | 'P' | 'Y' => CommandResult::Token(Token::YankPaste),
-            'x' | 'J' | 'X' => CommandResult::Token(Token::TextManipulationBasic(count_i32)),
-            'D' => CommandResult::Token(Token::DeleteText(count_i32)),
-            's' | 'S' | 'C' | '~' => CommandResult::Token(Token::TextManipulationAdvanced),
+            '$' => CommandResult::Token(Token::JumpToLinePosition(count_i32)),
+            '^' => CommandResult::Token(Token::JumpToLinePosition(1)),
+            'p' | 'P' => CommandResult::Token(Token::YankPaste(None)),
+            'J' => CommandResult::Token(Token::TextManipulationBasic(count_i32)),
+            // x, X, s, S, C, D and Y are shorthands for an operator and motion
+            'x' | 'X' | 's' | 'S' | 'C' | 'D' | 'Y' => {
+                CommandResult::Token(Self::operator_alias(ch, count))
+            }
+            'i' | 'a' => CommandResult::Token(Token::InsertBasic(count_i32)),
+            'I' | 'A' | 'o' | 'O' => CommandResult::Token(Token::InsertAdvanced(count_i32)),
+            '~' => CommandResult::Token(Token::TextManipulationAdvanced),
             'n' | 'N' | ';' | ',' => CommandResult::Token(Token::SearchRepeat),
+            '*' | '#' => CommandResult::Token(Token::SearchWord(count_i32)),
             '%' => CommandResult::Token(Token::JumpFromContext),
-            'f' | 'F' | 't' | 'T' => CommandResult::ConsumeNextOptional(
-                Token::JumpToHorizontal,
-                Token::Unhandled(ch.to_string()),
-            ),
-            'r' => CommandResult::ConsumeNextOptional(
-                Token::TextManipulationBasic(count_i32),
-                Token::Unhandled("r".into()),
-            ),
-            'm' => CommandResult::ConsumeNextOptional(Token::Marks, Token::Unhandled("m".into())),
-            '\'' => CommandResult::ConsumeNextOptional(Token::Marks, Token::Unhandled("'".into())),
-            '`' => CommandResult::ConsumeNextOptional(Token::Marks, Token::Unhandled("`".into())),
+            'f' | 'F' | 't' | 'T' => CommandResult::ConsumeNextOptional(Token::JumpToHorizontal),
+            'r' => CommandResult::ConsumeNextOptional(Token::TextManipulationBasic(count_i32)),
+            'm' => CommandResult::ConsumeNextOptional(Token::Marks),
             _ => CommandResult::NotSimple,
         }
     }
 
-    /// Skip a duplicate character if it matches the expected char.
-    /// This handles Neovim's `vim.on_key` replay behavior where motion
-    /// keys typed during operator-pending mode are echoed a second time
-    /// in the keystroke stream.
-    fn skip_if_duplicate(&mut self, ch: char) {
-        if self.input.peek() == Some(&ch) {
-            self.input.next();
+    /// Drop the copy `vim.on_key` reports of the keys from `group_start` on,
+    /// if the input continues with one. Input ending partway through a copy
+    /// counts as looking past the end, so the command is held back.
+    fn skip_echo(&mut self, group_start: usize) {
+        let group = &self.source[group_start..self.offset()];
+        if group.is_empty() {
+            return;
+        }
+        if self.input.rest.starts_with(group) {
+            let end = self.offset() + group.len();
+            while self.offset() < end && self.input.next().is_some() {}
+        } else if group.starts_with(self.input.rest) {
+            self.input.hit_end = true;
         }
     }
 
-    /// Deduplicate a motion count digit string that may have been doubled
-    /// by Neovim's `vim.on_key` replay. If the digit string has even length
-    /// and the first half equals the second half, returns the count from the
-    /// first half only. Otherwise returns the full accumulated count.
-    ///
-    /// Examples: "33" → 3, "1212" → 12, "3" → 3, "123" → 123
-    fn dedup_motion_count(digit_str: &str) -> u32 {
-        let len = digit_str.len();
-        if len.is_multiple_of(2) && len >= 2 {
-            let (first_half, second_half) = digit_str.split_at(len / 2);
-            if first_half == second_half {
-                return first_half.parse::<u32>().unwrap_or(999).min(999);
-            }
+    /// Whether `operator`'s keys after it are reported with `echo`
+    fn operator_echoes(operator: Operator, echo: Echo) -> bool {
+        echo::echoes(
+            &Self::operator_token(operator, 1, None, None),
+            Self::operator_to_keys(operator),
+            echo,
+        )
+    }
+
+    /// Check if a character names a register for a `"` prefix
+    fn is_register_char(ch: char) -> bool {
+        ch.is_ascii_alphanumeric()
+            || matches!(
+                ch,
+                '"' | '-' | '_' | '+' | '*' | '.' | ':' | '/' | '%' | '#' | '='
+            )
+    }
+
+    /// Attach a `"{reg}` prefix to the operator, delete and paste tokens that use it.
+    /// Other commands keep their token unchanged (e.g. `"ax`).
+    fn with_register(token: Token, register: char) -> Token {
+        match token {
+            Token::Operator(action) => Token::Operator(OperatorAction {
+                register: Some(register),
+                ..action
+            }),
+            Token::YankPaste(_) => Token::YankPaste(Some(register)),
+            Token::DeleteText(count, _) => Token::DeleteText(count, Some(register)),
+            other => other,
         }
-        digit_str.parse::<u32>().unwrap_or(999).min(999)
     }
 
     /// Check if a character is a valid text object specifier
@@ -341,19 +1214,29 @@ impl<'a> Lexer<'a> {
         )
     }
 
-    /// Handle operator pending state - process motion after d, y, or c
-    fn handle_operator_motion(&mut self, operator: Operator, count: u32) -> Token {
-        let Some(ch) = self.input.next() else {
-            // End of input - incomplete operator
-            return Token::Unhandled(Self::operator_to_char(operator).to_string());
+    /// Handle operator pending state - process motion after d, y, or c.
+    /// None when `|escape|` or `<C-C>` cancelled the operator.
+    fn handle_operator_motion(&mut self, operator: Operator, count: u32) -> Option<Token> {
+        let motion_start = self.offset();
+        let echo_start = Self::operator_echoes(operator, Echo::Motion).then_some(motion_start);
+        let ch = match self.next_arg_char(Self::operator_to_keys(operator)) {
+            Ok(ch) => ch,
+            Err(token) => return token,
         };
 
         // Handle doubled operator (dd, yy, cc) - line operation
-        let is_doubled = ch == Self::operator_to_char(operator);
+        let is_doubled = Self::operator_line_char(operator) == Some(ch);
 
         if is_doubled {
-            self.skip_if_duplicate(ch);
-            return Self::operator_to_token(operator, count);
+            if let Some(start) = echo_start {
+                self.skip_echo(start);
+            }
+            return Some(Self::operator_token(
+                operator,
+                count,
+                Some(Motion::Line),
+                None,
+            ));
         }
 
         // Handle motion count (e.g., d3w)
@@ -374,212 +1257,393 @@ impl<'a> Lexer<'a> {
                     break;
                 }
             }
-            // Deduplicate replayed count digits (e.g., d3w → d33w).
-            // Neovim replays the count digits, so "33" is really "3" doubled.
-            // If the digit string has even length and the first half equals the
-            // second half, use only the first half as the real count.
-            let motion_count = Self::dedup_motion_count(&motion_count_str);
+            // The echoed count is read with it (d3w arrives as d33w)
+            if Self::operator_echoes(operator, Echo::MotionCount) {
+                motion_count = echo::unrepeat(&motion_count_str)
+                    .parse::<u32>()
+                    .map_or(999, |count| count.min(999));
+            }
             let total_count = count.saturating_mul(motion_count);
             return self.handle_operator_with_motion(operator, total_count);
         }
 
         // Handle text objects (i/a + object)
+        if (ch == 'i' || ch == 'a') && self.take_cancel() {
+            return None;
+        }
         if (ch == 'i' || ch == 'a')
             && let Some(&obj_ch) = self.input.peek()
             && Self::is_text_object_char(obj_ch)
         {
             self.input.next(); // consume the object char
-            // Skip replayed text object chars (e.g., ciw → ciwiw)
-            self.skip_if_duplicate(ch);
-            self.skip_if_duplicate(obj_ch);
-            return Self::operator_to_token(operator, count);
+            if let Some(start) = echo_start {
+                self.skip_echo(start);
+            }
+            let text_object = TextObject {
+                around: ch == 'a',
+                object: obj_ch,
+            };
+            return Some(Self::operator_token(
+                operator,
+                count,
+                None,
+                Some(text_object),
+            ));
         }
 
         // Handle regular motions
-        self.handle_operator_char_motion(operator, count, ch, true)
+        self.handle_operator_char_motion(operator, count, ch, echo_start)
     }
 
     /// Handle operator with a motion character.
-    /// `skip_dupes` controls whether to consume replayed motion chars from
-    /// Neovim's `vim.on_key` echo. When a motion count was already parsed
-    /// (e.g., `d3w`), the motion char is not replayed, so `skip_dupes` is false.
+    /// `echo_start` is where the motion started when `vim.on_key` echoes it,
+    /// None when it is not echoed (after a motion count, `d3w`).
     fn handle_operator_char_motion(
         &mut self,
         operator: Operator,
         count: u32,
         ch: char,
-        skip_dupes: bool,
-    ) -> Token {
+        echo_start: Option<usize>,
+    ) -> Option<Token> {
+        let keys = Self::operator_to_keys(operator);
+
         // Handle line operation: motion char matches operator (e.g., d3d = delete 3 lines)
-        if ch == Self::operator_to_char(operator) {
-            return Self::operator_to_token(operator, count);
+        if Self::operator_line_char(operator) == Some(ch) {
+            return Some(Self::operator_token(
+                operator,
+                count,
+                Some(Motion::Line),
+                None,
+            ));
         }
 
-        match ch {
-            'w' | 'W' | 'e' | 'E' | 'b' | 'B' | '$' | '^' | '0' | 'j' | 'k' | 'h' | 'l' => {
-                if skip_dupes {
-                    self.skip_if_duplicate(ch);
+        let motion = match ch {
+            'w' | 'W' | 'e' | 'E' | 'b' | 'B' | '$' | '^' | '0' | 'j' | 'k' | 'h' | 'l' | 'G'
+            | '{' | '}' | '(' | ')' => {
+                if let Some(start) = echo_start {
+                    self.skip_echo(start);
                 }
-                Self::operator_to_token(operator, count)
+                Motion::Key(ch)
             }
             'f' | 'F' | 't' | 'T' => {
-                if skip_dupes {
-                    // Skip replayed f/F/t/T command char before consuming the target
-                    // e.g., dfa → dffa: skip the replayed f, then consume target a
-                    self.skip_if_duplicate(ch);
-                }
-                if self.input.next().is_some() {
-                    Self::operator_to_token(operator, count)
-                } else {
-                    Token::Unhandled(format!("{}{ch}", Self::operator_to_char(operator)))
+                // Only the f/F/t/T key is echoed, not the target (dfa → dffa)
+                if let Some(start) = echo_start {
+                    self.skip_echo(start);
                 }
+                let target = match self.next_arg_key() {
+                    Some(ArgKey::Char(target)) => target,
+                    Some(ArgKey::Special(key)) => match key.typed_char() {
+                        Some(target) => target,
+                        None => {
+                            return Some(Token::Unhandled(format!("{keys}{ch}{}", key.notation())));
+                        }
+                    },
+                    Some(ArgKey::Cancel) => return None,
+                    None => return Some(Token::Unhandled(format!("{keys}{ch}"))),
+                };
+                Motion::Find(ch, target)
             }
             'g' => {
-                if let Some(&next_ch) = self.input.peek() {
-                    self.input.next();
-                    match next_ch {
-                        'g' | 'j' | 'k' | '$' | '^' | '0' | 'e' | 'E' => {
-                            if skip_dupes {
-                                // Skip replayed g-motion (g + next_ch)
-                                self.skip_if_duplicate('g');
-                                self.skip_if_duplicate(next_ch);
-                            }
-                            Self::operator_to_token(operator, count)
+                let next_ch = match self.next_arg_char(&format!("{keys}g")) {
+                    Ok(next_ch) => next_ch,
+                    Err(token) => return token,
+                };
+                match next_ch {
+                    'g' | 'j' | 'k' | '$' | '^' | '0' | '_' | 'e' | 'E' => {
+                        if let Some(start) = echo_start {
+                            self.skip_echo(start);
                         }
-                        _ => Token::Unhandled(format!(
-                            "{}g{next_ch}",
-                            Self::operator_to_char(operator)
-                        )),
+                        Motion::G(next_ch)
                     }
-                } else {
-                    Token::Unhandled(format!("{}g", Self::operator_to_char(operator)))
+                    // Doubled two-key operator (gqgq, g?g?, gugu) - line operation
+                    _ if keys == format!("g{next_ch}") => Motion::Line,
+                    _ => return Some(Token::Unhandled(format!("{keys}g{next_ch}"))),
                 }
             }
-            _ => Token::Unhandled(format!("{}{ch}", Self::operator_to_char(operator))),
+            '/' | '?' => return self.handle_operator_search(operator, count, ch),
+            _ => return Some(Token::Unhandled(format!("{keys}{ch}"))),
+        };
+        Some(Self::operator_token(operator, count, Some(motion), None))
+    }
+
+    /// Handle an operator applied up to a search match (`d/foo|enter|`).
+    /// The operator only runs once the pattern is entered, so leaving the
+    /// command line any other way cancels it.
+    /// The caller has already consumed the `/` or `?`.
+    fn handle_operator_search(
+        &mut self,
+        operator: Operator,
+        count: u32,
+        direction: char,
+    ) -> Option<Token> {
+        loop {
+            if let Some(completed) = self.check_command_terminator() {
+                return completed.then(|| {
+                    Self::operator_token(operator, count, Some(Motion::Search(direction)), None)
+                });
+            }
+            if self.take_cancel() || self.head_mode().is_some_and(|mode| mode != Mode::Cmdline) {
+                return None;
+            }
+            // Special keys edit the pattern rather than end it
+            if let Some((_, rest)) = self.peek_special_key() {
+                self.input = rest;
+                continue;
+            }
+            if self.input.next().is_none() {
+                return Some(Token::Unhandled(format!(
+                    "{}{direction}",
+                    Self::operator_to_keys(operator)
+                )));
+            }
         }
     }
 
     /// Handle operator with accumulated motion count.
     /// When a motion count was present (e.g., `d3w`), the motion char itself
-    /// is not replayed by Neovim, so we pass `skip_dupes = false`.
-    fn handle_operator_with_motion(&mut self, operator: Operator, count: u32) -> Token {
-        if let Some(ch) = self.input.next() {
-            self.handle_operator_char_motion(operator, count, ch, false)
-        } else {
-            Token::Unhandled(Self::operator_to_char(operator).to_string())
+    /// is not echoed by Neovim.
+    fn handle_operator_with_motion(&mut self, operator: Operator, count: u32) -> Option<Token> {
+        match self.next_arg_char(Self::operator_to_keys(operator)) {
+            Ok(ch) => self.handle_operator_char_motion(operator, count, ch, None),
+            Err(token) => token,
+        }
+    }
+
+    /// Handle `'{mark}` and `` `{mark} `` jumps. Doubling the key (`''`, ` `` `)
+    /// jumps back to the position before the latest jump.
+    /// The caller has already consumed the first quote.
+    fn handle_mark_jump(&mut self, quote: char) -> Option<Token> {
+        match self.next_arg_char(&quote.to_string()) {
+            Ok(ch) if ch == quote => Some(Token::JumpList),
+            Ok(_) => Some(Token::Marks),
+            Err(token) => token,
         }
     }
 
-    /// Handle case operator motion (g~, gu, gU + motion)
-    fn handle_case_operator_motion(&mut self, operator: &str, count: u32) -> Token {
-        let Some(ch) = self.input.next() else {
-            // End of input - incomplete case operator
-            return Token::Unhandled(operator.to_string());
+    /// Handle the `[` and `]` motions. Both directions map to the same kind,
+    /// `count` repeats the jump (`3]q`).
+    /// The caller has already consumed the opening bracket.
+    fn handle_bracket_motion(&mut self, bracket: char, count: u32) -> Option<Token> {
+        let ch = match self.next_arg_char(&bracket.to_string()) {
+            Ok(ch) => ch,
+            Err(token) => return token,
         };
+        let kind = match ch {
+            '[' | ']' => BracketKind::Section,
+            'm' | 'M' => BracketKind::Method,
+            '{' | '}' | '(' | ')' => BracketKind::Block,
+            'q' | 'Q' | 'l' | 'L' => BracketKind::Quickfix,
+            'd' | 'D' => BracketKind::Diagnostic,
+            'c' => BracketKind::Hunk,
+            'b' | 'B' => BracketKind::Buffer,
+            _ => return Some(Token::Unhandled(format!("{bracket}{ch}"))),
+        };
+        Some(Token::BracketJump(kind, i32::try_from(count).unwrap()))
+    }
+
+    /// Handle the `z` commands: scrolling (`zz`, `zt`, `zb`) and folds.
+    /// `zf` is an operator and takes a motion like `d` does.
+    /// The caller has already consumed the `z`.
+    fn handle_z_command(&mut self, count: u32) -> Option<Token> {
+        let token = match self.next_arg_char("z") {
+            Ok('z' | 't' | 'b') => Token::CameraMovement,
+            Ok('f') => return self.handle_operator_motion(Operator::Fold, count),
+            Ok('F') => Token::FoldCreate,
+            Ok('d' | 'D' | 'E') => Token::FoldDelete,
+            Ok(
+                'o' | 'O' | 'c' | 'C' | 'a' | 'A' | 'v' | 'x' | 'X' | 'm' | 'M' | 'r' | 'R' | 'n'
+                | 'N' | 'i',
+            ) => Token::FoldToggle,
+            Ok('j' | 'k') => Token::FoldMove(i32::try_from(count).unwrap_or(1)),
+            Ok(ch) => Token::Unhandled(format!("z{ch}")),
+            Err(token) => return token,
+        };
+        Some(token)
+    }
 
-        // Handle motion count (e.g., gu3w)
-        if ch.is_ascii_digit() && ch != '0' {
-            let mut motion_count_str = String::from(ch);
-            let mut motion_count = ch.to_digit(10).unwrap();
-            while let Some(&next_ch) = self.input.peek() {
-                if next_ch.is_ascii_digit() {
-                    self.input.next();
-                    motion_count_str.push(next_ch);
-                    motion_count = motion_count
-                        .saturating_mul(10)
-                        .saturating_add(next_ch.to_digit(10).unwrap());
-                    if motion_count > 999 {
-                        motion_count = 999;
-                    }
-                } else {
-                    break;
-                }
+    /// Handle `q` - either `q{reg}` to start recording a macro, or a bare `q`
+    /// to stop the recording in progress.
+    /// The caller has already consumed the `q`.
+    fn handle_macro_record(&mut self) -> Option<Token> {
+        if self.recording_macro {
+            self.recording_macro = false;
+            return Some(Token::MacroRecordStop);
+        }
+
+        match self.next_arg_char("q") {
+            Ok(reg) if reg.is_ascii_alphanumeric() || reg == '"' => {
+                self.recording_macro = true;
+                Some(Token::MacroRecordStart)
             }
-            // Deduplicate replayed count digits
-            let motion_count = Self::dedup_motion_count(&motion_count_str);
-            return self
-                .handle_case_operator_with_motion(operator, count.saturating_mul(motion_count));
+            // q:, q/ and q? open the command-line window instead
+            Ok(ch) => Some(Token::Unhandled(format!("q{ch}"))),
+            Err(token) => token,
         }
+    }
 
-        // Handle regular motions
-        self.handle_case_operator_char_motion(operator, ch, true)
+    /// Handle `@{reg}` / `@@` macro playback.
+    /// The caller has already consumed the `@`.
+    fn handle_macro_playback(&mut self, count: u32) -> Option<Token> {
+        match self.next_arg_char("@") {
+            Ok(reg)
+                if reg.is_ascii_alphanumeric()
+                    || matches!(reg, '"' | '.' | '=' | '*' | '+' | '@' | ':') =>
+            {
+                Some(Token::MacroPlayback(i32::try_from(count).unwrap()))
+            }
+            Ok(ch) => Some(Token::Unhandled(format!("@{ch}"))),
+            Err(token) => token,
+        }
     }
 
-    /// Handle case operator with a motion character.
-    /// Note: count is not used here because `TextManipulationAdvanced` doesn't carry count info.
-    /// The motion itself (not the case operator) determines the range.
-    /// `skip_dupes` controls whether to consume replayed motion chars.
-    fn handle_case_operator_char_motion(
-        &mut self,
-        operator: &str,
-        ch: char,
-        skip_dupes: bool,
-    ) -> Token {
-        match ch {
-            'w' | 'W' | 'e' | 'E' | 'b' | 'B' | '$' | '^' | '0' | 'j' | 'k' | 'h' | 'l' => {
-                if skip_dupes {
-                    self.skip_if_duplicate(ch);
-                }
-                Token::TextManipulationAdvanced
+    /// Handle a visual selection started by `v`, `V` or `<C-V>`.
+    /// Follows the motions and text objects that shape the selection until an
+    /// operator, `|escape|`, `|enter|` or `<C-C>` ends it. The returned token
+    /// carries the number of motions and text objects used to build the
+    /// selection, and the register a yank, delete or change goes through.
+    /// Escaping the argument of a key inside the selection (`f|escape|`) only
+    /// cancels that key.
+    #[allow(clippy::too_many_lines)]
+    fn handle_visual_mode(&mut self, mut kind: VisualKind) -> Token {
+        let mut motions: i32 = 0;
+        let mut register = None;
+
+        loop {
+            // Neovim left visual mode without a key the lexer knows (<C-C>, a mapping)
+            if self.head_mode().is_some_and(|mode| !mode.is_visual()) {
+                return Token::VisualSelection(motions);
             }
-            'f' | 'F' | 't' | 'T' => {
-                if skip_dupes {
-                    // Skip replayed f/F/t/T before consuming target
-                    self.skip_if_duplicate(ch);
+
+            let Some(ch) = self.input.next() else {
+                // End of input - selection still open
+                return Token::VisualSelection(motions);
+            };
+
+            match ch {
+                '1'..='9' => {
+                    // A count only scales the motion that follows it
+                    while self.input.peek().is_some_and(char::is_ascii_digit) {
+                        self.input.next();
+                    }
                 }
-                if self.input.next().is_some() {
-                    Token::TextManipulationAdvanced
-                } else {
-                    Token::Unhandled(format!("{operator}{ch}"))
+                'h' | 'j' | 'k' | 'l' | 'w' | 'W' | 'e' | 'E' | 'b' | 'B' | '$' | '^' | '0'
+                | '%' | 'G' | 'H' | 'M' | 'L' | 'n' | 'N' | ';' | ',' | 'o' | 'O' | '{' | '}'
+                | '(' | ')' => {
+                    motions += 1;
                 }
-            }
-            'g' => {
-                if let Some(&next_ch) = self.input.peek() {
-                    self.input.next();
-                    match next_ch {
-                        'g' | 'j' | 'k' | '$' | '^' | '0' | 'e' | 'E' => {
-                            if skip_dupes {
-                                self.skip_if_duplicate('g');
-                                self.skip_if_duplicate(next_ch);
+                'f' | 'F' | 't' | 'T' => match self.next_arg_key() {
+                    Some(ArgKey::Char(_)) => motions += 1,
+                    Some(ArgKey::Special(key)) if key.typed_char().is_some() => motions += 1,
+                    Some(_) => {}
+                    None => return Token::VisualSelection(motions),
+                },
+                'g' => match self.next_arg_key() {
+                    Some(ArgKey::Char('g' | 'j' | 'k' | 'e' | 'E' | '_' | '0' | '^' | '$')) => {
+                        motions += 1;
+                    }
+                    Some(ArgKey::Char('q' | 'w' | '?' | '~' | 'u' | 'U' | 'J')) => {
+                        return Token::VisualManipulation(motions, None);
+                    }
+                    // g<C-A> and g<C-X> count up or down across the selected lines
+                    Some(ArgKey::Special(SpecialKey::Ctrl('A' | 'X'))) => {
+                        return Token::IncrementSequence;
+                    }
+                    Some(_) => {}
+                    None => return Token::VisualSelection(motions),
+                },
+                'i' | 'a' => {
+                    if let Some(&obj_ch) = self.input.peek()
+                        && Self::is_text_object_char(obj_ch)
+                    {
+                        self.input.next();
+                        motions += 1;
+                    }
+                }
+                'v' | 'V' => {
+                    // Pressing the current mode key again leaves visual mode,
+                    // any other visual key switches the selection kind
+                    let pressed = if ch == 'v' {
+                        VisualKind::Char
+                    } else {
+                        VisualKind::Line
+                    };
+                    if pressed == kind {
+                        return Token::VisualSelection(motions);
+                    }
+                    kind = pressed;
+                }
+                'z' => match self.next_arg_key() {
+                    Some(ArgKey::Char('f')) => return Token::FoldCreate,
+                    Some(_) => {}
+                    None => return Token::VisualSelection(motions),
+                },
+                // Register for the operator that ends the selection
+                '"' => match self.next_arg_key() {
+                    Some(ArgKey::Char(reg)) if Self::is_register_char(reg) => register = Some(reg),
+                    Some(_) => {}
+                    None => return Token::VisualSelection(motions),
+                },
+                // p and P swap the selection for a register's text
+                'y' | 'Y' | 'p' | 'P' => return Token::VisualYank(motions, register),
+                'd' | 'x' | 'c' | 's' | 'D' | 'X' | 'C' | 'S' | 'R' => {
+                    return Token::VisualManipulation(motions, register);
+                }
+                '~' | 'u' | 'U' | 'J' | '>' | '=' | 'I' | 'A' => {
+                    return Token::VisualManipulation(motions, None);
+                }
+                '!' => {
+                    self.skip_filter_command();
+                    return Token::VisualManipulation(motions, None);
+                }
+                'r' => match self.next_arg_key() {
+                    Some(ArgKey::Char(_)) => return Token::VisualManipulation(motions, None),
+                    Some(ArgKey::Special(key)) if key.typed_char().is_some() => {
+                        return Token::VisualManipulation(motions, None);
+                    }
+                    Some(_) => {}
+                    None => return Token::VisualSelection(motions),
+                },
+                '<' => {
+                    match self.try_parse_key_notation() {
+                        // Plain '<' is the shift-left operator
+                        None => return Token::VisualManipulation(motions, None),
+                        Some(SpecialKey::Ctrl('U' | 'D' | 'F' | 'B')) => motions += 1,
+                        Some(SpecialKey::Ctrl('A' | 'X')) => return Token::Increment(1),
+                        Some(SpecialKey::Ctrl('V')) => {
+                            if kind == VisualKind::Block {
+                                return Token::VisualSelection(motions);
                             }
-                            Token::TextManipulationAdvanced
+                            kind = VisualKind::Block;
+                        }
+                        Some(SpecialKey::Named("escape" | "enter") | SpecialKey::Ctrl('C')) => {
+                            return Token::VisualSelection(motions);
                         }
-                        _ => Token::Unhandled(format!("{operator}g{next_ch}")),
+                        _ => {}
                     }
-                } else {
-                    Token::Unhandled(format!("{operator}g"))
                 }
-            }
-            'i' | 'a' => {
-                if let Some(&obj_ch) = self.input.peek()
-                    && Self::is_text_object_char(obj_ch)
-                {
-                    self.input.next(); // consume the object char
-                    if skip_dupes {
-                        self.skip_if_duplicate(ch);
-                        self.skip_if_duplicate(obj_ch);
-                    }
-                    return Token::TextManipulationAdvanced;
+                '|' => match self.try_parse_pipe_delimited() {
+                    Some("escape" | "enter") => return Token::VisualSelection(motions),
+                    Some(_) => {}
+                    // A literal '|' is the column motion
+                    None => motions += 1,
+                },
+                ':' => {
+                    // The selection becomes the range of an Ex command. Neovim
+                    // fills in '<,'> itself, so it never arrives as typed keys.
+                    self.state = State::CommandMode {
+                        content: VISUAL_RANGE.to_string(),
+                    };
+                    return Token::VisualSelection(motions);
                 }
-                Token::Unhandled(format!("{operator}{ch}"))
+                _ => {}
             }
-            _ => Token::Unhandled(format!("{operator}{ch}")),
-        }
-    }
-
-    /// Handle case operator with accumulated motion count.
-    /// When a motion count was present, the motion char itself is not
-    /// replayed by Neovim, so we pass `skip_dupes = false`.
-    fn handle_case_operator_with_motion(&mut self, operator: &str, _count: u32) -> Token {
-        if let Some(ch) = self.input.next() {
-            self.handle_case_operator_char_motion(operator, ch, false)
-        } else {
-            Token::Unhandled(operator.to_string())
         }
     }
 
+    /// Lex the next command. None at the end of input, or once the keys of a
+    /// command that was cancelled have been consumed.
     #[allow(clippy::too_many_lines)]
-    pub fn next_token(&mut self) -> Option<Token> {
+    fn lex_token(&mut self) -> Option<Token> {
         let mode_content = match &mut self.state {
             State::CommandMode { content } => Some((0, std::mem::take(content))),
             State::SearchMode { content } => Some((1, std::mem::take(content))),
@@ -591,18 +1655,16 @@ impl<'a> Lexer<'a> {
             if mode_type == 2 {
                 loop {
                     // Check for |escape| terminator
-                    if let Some(&'|') = self.input.peek() {
-                        let mut peek_iter = self.input.clone();
-                        peek_iter.next(); // skip '|'
-                        let chars: String = peek_iter.take(7).collect();
-                        if chars == "escape|" {
-                            // Consume |escape| from real iterator
-                            for _ in 0..8 {
-                                self.input.next();
-                            }
-                            self.state = State::None;
-                            return Some(Token::TextManipulationAdvanced);
-                        }
+                    if let Some((SpecialKey::Named("escape"), rest)) = self.peek_special_key() {
+                        self.input = rest;
+                        self.state = State::None;
+                        return Some(Token::TextManipulationAdvanced);
+                    }
+
+                    // Replace mode ended without |escape| (<C-C>, a mapping)
+                    if self.head_mode().is_some_and(|mode| mode != Mode::Replace) {
+                        self.state = State::None;
+                        return Some(Token::TextManipulationAdvanced);
                     }
 
                     if let Some(ch) = self.input.next() {
@@ -626,22 +1688,28 @@ impl<'a> Lexer<'a> {
                     return Some(Token::CommandSearch(completed));
                 }
 
-                // Handle pipe-delimited keys within command/search (like |space|)
-                if let Some(&'|') = self.input.peek() {
-                    self.input.next(); // consume '|'
-                    if let Some(key) = self.try_parse_pipe_delimited() {
-                        match key.as_str() {
-                            "space" => content.push(' '),
-                            "tab" => content.push('\t'),
-                            "backspace" => {
-                                content.pop();
-                            }
-                            _ => {} // "enter" should have been caught by check_command_terminator
+                // The command line closed without a terminator the lexer knows
+                // (<C-C>, a mapping), so the command was never run
+                if self.head_mode().is_some_and(|mode| mode != Mode::Cmdline) {
+                    self.state = State::None;
+                    if mode_type == 0 {
+                        return Some(Self::classify_command(&content, false));
+                    }
+                    return Some(Token::CommandSearch(false));
+                }
+
+                // Handle special keys within command/search (like |space|).
+                // Control keys edit the command line rather than add text.
+                if let Some((key, rest)) = self.peek_special_key() {
+                    self.input = rest;
+                    match key {
+                        SpecialKey::Named("space") => content.push(' '),
+                        SpecialKey::Named("tab") => content.push('\t'),
+                        SpecialKey::Named("backspace") => {
+                            content.pop();
                         }
-                        continue;
+                        _ => {} // "enter" should have been caught by check_command_terminator
                     }
-                    // Not a valid pipe sequence, treat '|' as literal
-                    content.push('|');
                     continue;
                 }
 
@@ -662,17 +1730,17 @@ impl<'a> Lexer<'a> {
             State::CommandMode { .. } | State::SearchMode { .. } | State::ReplaceMode { .. } => {
                 unreachable!("Already handled above")
             }
-            State::CaseOperatorPending {
-                ref operator,
-                count,
-            } => {
-                let op = operator.clone();
+            State::OperatorPending { operator, count } => {
                 self.state = State::None;
-                Some(self.handle_case_operator_motion(&op, count))
+                let token = self.handle_operator_motion(operator, count)?;
+                if matches!(operator, Operator::Filter) && !matches!(token, Token::Unhandled(_)) {
+                    self.skip_filter_command();
+                }
+                Some(token)
             }
-            State::OperatorPending { operator, count } => {
+            State::VisualMode { kind } => {
                 self.state = State::None;
-                Some(self.handle_operator_motion(operator, count))
+                Some(self.handle_visual_mode(kind))
             }
             State::AccumulatingCount(count) => {
                 if let Some(&ch) = self.input.peek() {
@@ -683,7 +1751,7 @@ impl<'a> Lexer<'a> {
                         self.state = State::AccumulatingCount(new_count);
 
                         // Continue accumulating
-                        self.next_token()
+                        self.lex_token()
                     } else {
                         // Non-digit encountered, process based on what it is
                         self.state = State::None;
@@ -696,17 +1764,19 @@ impl<'a> Lexer<'a> {
                                 self.input.next();
                                 return Some(token);
                             }
-                            CommandResult::ConsumeNextOptional(success, failure) => {
+                            CommandResult::ConsumeNextOptional(token) => {
                                 self.input.next(); // consume command char
-                                return if self.input.next().is_some() {
-                                    Some(success)
-                                } else {
-                                    Some(failure)
-                                };
+                                return self.consume_argument(&ch.to_string(), token);
                             }
                             CommandResult::NotSimple => {} // Fall through to special handling
                         }
 
+                        if let Some(operator) = Self::key_to_operator(ch) {
+                            self.input.next();
+                            self.state = State::OperatorPending { operator, count };
+                            return self.lex_token();
+                        }
+
                         // Special commands that need state transitions or complex handling
                         match ch {
                             'G' => {
@@ -715,95 +1785,114 @@ impl<'a> Lexer<'a> {
                             }
                             'g' => {
                                 self.input.next();
-                                match self.input.next() {
-                                    Some('j' | 'k') => Some(Token::MoveVerticalBasic(
-                                        i32::try_from(count).unwrap(),
-                                    )),
-                                    Some('g') => Some(Token::JumpToLineNumber(accumulated)),
-                                    Some('J') => Some(Token::TextManipulationBasic(
+                                match self.next_arg_char("g") {
+                                    Ok('j' | 'k') => Some(Token::MoveVerticalBasic(
                                         i32::try_from(count).unwrap(),
                                     )),
-                                    Some('~') => {
-                                        self.state = State::CaseOperatorPending {
-                                            operator: "g~".to_string(),
-                                            count,
-                                        };
-                                        self.next_token()
+                                    Ok('g') => Some(Token::JumpToLineNumber(accumulated)),
+                                    Ok(';' | ',') => Some(Token::JumpList),
+                                    Ok('t' | 'T') => Some(Token::BufferSwitch),
+                                    Ok('*' | '#') => {
+                                        Some(Token::SearchWord(i32::try_from(count).unwrap()))
                                     }
-                                    Some('u') => {
-                                        self.state = State::CaseOperatorPending {
-                                            operator: "gu".to_string(),
-                                            count,
-                                        };
-                                        self.next_token()
+                                    Ok('d' | 'D') => Some(Token::GotoDefinition),
+                                    Ok('I') => {
+                                        Some(Token::InsertBasic(i32::try_from(count).unwrap()))
+                                    }
+                                    Ok('i') => {
+                                        Some(Token::InsertAdvanced(i32::try_from(count).unwrap()))
                                     }
-                                    Some('U') => {
-                                        self.state = State::CaseOperatorPending {
-                                            operator: "gU".to_string(),
+                                    // 3g_ and 3g$ move to the end of a later line
+                                    Ok('_' | '$') => Some(Token::JumpToLinePosition(
+                                        i32::try_from(count).unwrap(),
+                                    )),
+                                    Ok('0' | '^' | 'm' | 'M') => Some(Token::JumpToLinePosition(1)),
+                                    Ok('J') => Some(Token::TextManipulationBasic(
+                                        i32::try_from(count).unwrap(),
+                                    )),
+                                    Ok(key @ ('q' | 'w' | '?' | '~' | 'u' | 'U')) => {
+                                        self.state = State::OperatorPending {
+                                            operator: Self::g_operator(key),
                                             count,
                                         };
-                                        self.next_token()
+                                        self.lex_token()
                                     }
-                                    Some(ch) => Some(Token::Unhandled(format!("g{ch}"))),
-                                    None => Some(Token::Unhandled("g".into())),
+                                    Ok(ch) => Some(Token::Unhandled(format!("g{ch}"))),
+                                    Err(token) => token,
                                 }
                             }
                             'z' => {
                                 self.input.next();
-                                match self.input.next() {
-                                    Some('z' | 't' | 'b') => Some(Token::CameraMovement),
-                                    Some(ch) => Some(Token::Unhandled(format!("z{ch}"))),
-                                    None => Some(Token::Unhandled("z".into())),
-                                }
+                                self.handle_z_command(count)
                             }
-                            'f' | 'F' | 't' | 'T' => {
+                            '[' | ']' => {
                                 self.input.next();
-                                if self.input.next().is_some() {
-                                    Some(Token::JumpToHorizontal)
-                                } else {
-                                    Some(Token::Unhandled(ch.to_string()))
-                                }
+                                self.handle_bracket_motion(ch, count)
                             }
-                            'r' => {
+                            '"' => {
                                 self.input.next();
-                                if self.input.next().is_some() {
-                                    Some(Token::TextManipulationBasic(
-                                        i32::try_from(count).unwrap(),
-                                    ))
-                                } else {
-                                    Some(Token::Unhandled("r".into()))
+                                match self.next_arg_char(&format!("{accumulated}\"")) {
+                                    Ok(_) if self.take_cancel() => None,
+                                    Ok(reg) if Self::is_register_char(reg) => {
+                                        // Keep the count for the command after the register (3"ayy)
+                                        self.state = State::AccumulatingCount(count);
+                                        self.accumulated_string = accumulated;
+                                        self.lex_token()
+                                            .map(|token| Self::with_register(token, reg))
+                                    }
+                                    Ok(ch) => {
+                                        Some(Token::Unhandled(format!("{accumulated}\"{ch}")))
+                                    }
+                                    Err(token) => token,
                                 }
                             }
-                            'd' => {
+                            '\'' | '`' => {
                                 self.input.next();
-                                self.state = State::OperatorPending {
-                                    operator: Operator::Delete,
-                                    count,
-                                };
-                                self.next_token()
+                                self.handle_mark_jump(ch)
                             }
-                            'y' => {
+                            'q' => {
                                 self.input.next();
-                                self.state = State::OperatorPending {
-                                    operator: Operator::Yank,
-                                    count,
-                                };
-                                self.next_token()
+                                self.handle_macro_record()
+                            }
+                            '@' => {
+                                self.input.next();
+                                self.handle_macro_playback(count)
                             }
-                            'c' => {
+                            'v' | 'V' => {
                                 self.input.next();
-                                self.state = State::OperatorPending {
-                                    operator: Operator::Change,
-                                    count,
+                                self.state = State::VisualMode {
+                                    kind: if ch == 'v' {
+                                        VisualKind::Char
+                                    } else {
+                                        VisualKind::Line
+                                    },
                                 };
-                                self.next_token()
+                                self.lex_token()
+                            }
+                            // 5| jumps to column 5, 5|up| is a count before an arrow key
+                            '|' if !self.at_pipe_key() => {
+                                self.input.next();
+                                Some(Token::JumpToLinePosition(i32::try_from(count).unwrap()))
                             }
                             '<' => {
                                 self.input.next();
-                                if let Some(ctrl_char) = self.try_parse_control_sequence() {
-                                    Some(self.handle_control_sequence(ctrl_char, count))
-                                } else {
-                                    Some(Token::Unhandled(accumulated))
+                                match self.try_parse_key_notation() {
+                                    Some(SpecialKey::Ctrl(ctrl_char)) => {
+                                        Some(self.handle_control_sequence(ctrl_char, count))
+                                    }
+                                    Some(SpecialKey::Named(key)) => {
+                                        Some(Self::special_key_token(key))
+                                    }
+                                    Some(SpecialKey::Other(notation)) => {
+                                        Some(Token::Unhandled(format!("{accumulated}{notation}")))
+                                    }
+                                    None => {
+                                        self.state = State::OperatorPending {
+                                            operator: Operator::Unindent,
+                                            count,
+                                        };
+                                        self.lex_token()
+                                    }
                                 }
                             }
                             _ => Some(Token::Unhandled(accumulated)),
@@ -822,118 +1911,120 @@ impl<'a> Lexer<'a> {
                 if ch.is_ascii_digit() && ch != '0' {
                     let count = self.accumulate_digit(ch);
                     self.state = State::AccumulatingCount(count);
-                    return self.next_token();
+                    return self.lex_token();
                 }
 
                 match Self::handle_simple_command(ch, 1) {
                     CommandResult::Token(token) => return Some(token),
-                    CommandResult::ConsumeNextOptional(success, failure) => {
-                        return if self.input.next().is_some() {
-                            Some(success)
-                        } else {
-                            Some(failure)
-                        };
+                    CommandResult::ConsumeNextOptional(token) => {
+                        return self.consume_argument(&ch.to_string(), token);
                     }
                     CommandResult::NotSimple => {} // Fall through to special handling
                 }
 
+                if let Some(operator) = Self::key_to_operator(ch) {
+                    self.state = State::OperatorPending { operator, count: 1 };
+                    return self.lex_token();
+                }
+
                 // Special commands that need state transitions or complex handling
                 match ch {
-                    '0' => Some(Token::Unhandled("0".into())),
+                    // A leading 0 is a motion, later zeros belong to a count
+                    '0' => Some(Token::JumpToLinePosition(1)),
                     'G' => Some(Token::JumpToLineNumber(String::new())),
-                    'g' => match self.input.next() {
-                        Some('j' | 'k') => Some(Token::MoveVerticalBasic(1)),
-                        Some('g') => Some(Token::JumpToLineNumber(String::new())),
-                        Some('J') => Some(Token::TextManipulationBasic(1)),
-                        Some('~') => {
-                            self.state = State::CaseOperatorPending {
-                                operator: "g~".to_string(),
-                                count: 1,
-                            };
-                            self.next_token()
-                        }
-                        Some('u') => {
-                            self.state = State::CaseOperatorPending {
-                                operator: "gu".to_string(),
-                                count: 1,
-                            };
-                            self.next_token()
-                        }
-                        Some('U') => {
-                            self.state = State::CaseOperatorPending {
-                                operator: "gU".to_string(),
+                    'g' => match self.next_arg_char("g") {
+                        Ok('j' | 'k') => Some(Token::MoveVerticalBasic(1)),
+                        Ok('g') => Some(Token::JumpToLineNumber(String::new())),
+                        Ok(';' | ',') => Some(Token::JumpList),
+                        Ok('t' | 'T') => Some(Token::BufferSwitch),
+                        Ok('*' | '#') => Some(Token::SearchWord(1)),
+                        Ok('d' | 'D') => Some(Token::GotoDefinition),
+                        Ok('I') => Some(Token::InsertBasic(1)),
+                        Ok('i') => Some(Token::InsertAdvanced(1)),
+                        Ok('_' | '0' | '^' | '$' | 'm' | 'M') => Some(Token::JumpToLinePosition(1)),
+                        Ok('J') => Some(Token::TextManipulationBasic(1)),
+                        Ok(key @ ('q' | 'w' | '?' | '~' | 'u' | 'U')) => {
+                            self.state = State::OperatorPending {
+                                operator: Self::g_operator(key),
                                 count: 1,
                             };
-                            self.next_token()
+                            self.lex_token()
                         }
-                        Some(ch) => Some(Token::Unhandled(format!("g{ch}"))),
-                        None => Some(Token::Unhandled("g".into())),
+                        Ok(ch) => Some(Token::Unhandled(format!("g{ch}"))),
+                        Err(token) => token,
                     },
                     'R' => {
                         self.state = State::ReplaceMode {
                             content: String::new(),
                         };
-                        self.next_token()
+                        self.lex_token()
                     }
-                    'z' => match self.input.next() {
-                        Some('z' | 't' | 'b') => Some(Token::CameraMovement),
-                        Some(ch) => Some(Token::Unhandled(format!("z{ch}"))),
-                        None => Some(Token::Unhandled("z".into())),
+                    'z' => self.handle_z_command(1),
+                    '[' | ']' => self.handle_bracket_motion(ch, 1),
+                    '"' => match self.next_arg_char("\"") {
+                        Ok(_) if self.take_cancel() => None,
+                        Ok(reg) if Self::is_register_char(reg) => {
+                            let command_start = self.offset();
+                            match self.lex_token() {
+                                Some(token) => Some(Self::with_register(token, reg)),
+                                // Cancelled after the register ("ad|escape|)
+                                None if self.offset() > command_start => None,
+                                None => Some(Token::Unhandled(format!("\"{reg}"))),
+                            }
+                        }
+                        Ok(ch) => Some(Token::Unhandled(format!("\"{ch}"))),
+                        Err(token) => token,
                     },
-                    'd' => {
-                        self.state = State::OperatorPending {
-                            operator: Operator::Delete,
-                            count: 1,
-                        };
-                        self.next_token()
-                    }
-                    'y' => {
-                        self.state = State::OperatorPending {
-                            operator: Operator::Yank,
-                            count: 1,
+                    '\'' | '`' => self.handle_mark_jump(ch),
+                    'q' => self.handle_macro_record(),
+                    '@' => self.handle_macro_playback(1),
+                    'v' => {
+                        self.state = State::VisualMode {
+                            kind: VisualKind::Char,
                         };
-                        self.next_token()
+                        self.lex_token()
                     }
-                    'c' => {
-                        self.state = State::OperatorPending {
-                            operator: Operator::Change,
-                            count: 1,
+                    'V' => {
+                        self.state = State::VisualMode {
+                            kind: VisualKind::Line,
                         };
-                        self.next_token()
+                        self.lex_token()
                     }
-                    '<' => {
-                        if let Some(ctrl_char) = self.try_parse_control_sequence() {
+                    '<' => match self.try_parse_key_notation() {
+                        Some(SpecialKey::Ctrl(ctrl_char)) => {
                             Some(self.handle_control_sequence(ctrl_char, 1))
-                        } else {
-                            Some(Token::Unhandled("<".into()))
                         }
-                    }
+                        Some(SpecialKey::Named(key)) => Some(Self::special_key_token(key)),
+                        Some(SpecialKey::Other(notation)) => Some(Token::Unhandled(notation)),
+                        None => {
+                            self.state = State::OperatorPending {
+                                operator: Operator::Unindent,
+                                count: 1,
+                            };
+                            self.lex_token()
+                        }
+                    },
                     '|' => {
                         if let Some(key) = self.try_parse_pipe_delimited() {
-                            match key.as_str() {
-                                "escape" => Some(Token::Unhandled("|escape|".into())),
-                                _ => Some(Token::Unhandled(format!("|{key}|"))),
-                            }
+                            Some(Self::special_key_token(key))
                         } else {
-                            Some(Token::Unhandled("|".into()))
+                            // A literal '|' is the column motion
+                            Some(Token::JumpToLinePosition(1))
                         }
                     }
                     ':' => {
                         self.state = State::CommandMode {
                             content: String::new(),
                         };
-                        self.next_token()
+                        self.lex_token()
                     }
                     '/' | '?' => {
                         self.state = State::SearchMode {
                             content: String::new(),
                         };
-                        self.next_token()
+                        self.lex_token()
                     }
-                    // Intentionally unhandled commands fall through here:
-                    // - Insert mode: o, O, i, I, a, A (enter insert mode - handled by game)
-                    // - Visual mode: v, V (future consideration per SPEC.md)
-                    // - Macros: q, @ (future consideration per SPEC.md)
+                    // Intentionally unhandled commands fall through here.
                     // See module-level documentation for full details.
                     _ => Some(Token::Unhandled(ch.into())),
                 }
@@ -946,6 +2037,37 @@ impl<'a> Lexer<'a> {
 mod tests {
     use super::*;
 
+    /// Next token with operator actions mapped back to their legacy variant,
+    /// for tests that only check how a command is classified
+    fn next_legacy(lexer: &mut Lexer) -> Option<Token> {
+        lexer.next_token().map(|token| match token {
+            Token::Operator(action) => action.legacy_token(),
+            other => other,
+        })
+    }
+
+    fn action(
+        operator: Operator,
+        count: i32,
+        motion: Option<Motion>,
+        text_object: Option<TextObject>,
+    ) -> Token {
+        Token::Operator(OperatorAction {
+            operator,
+            motion,
+            text_object,
+            count,
+            register: None,
+        })
+    }
+
+    fn inner(object: char) -> TextObject {
+        TextObject {
+            around: false,
+            object,
+        }
+    }
+
     #[test]
     fn test_numeric_prefix_basic() {
         let mut lexer = Lexer::new("5j");
@@ -1006,7 +2128,10 @@ mod tests {
     #[test]
     fn test_leading_zero() {
         let mut lexer = Lexer::new("0j");
-        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "0"));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::JumpToLinePosition(1))
+        ));
         assert!(matches!(
             lexer.next_token(),
             Some(Token::MoveVerticalBasic(1))
@@ -1039,7 +2164,7 @@ mod tests {
             lexer.next_token(),
             Some(Token::MoveVerticalBasic(5))
         ));
-        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "a"));
+        assert!(matches!(lexer.next_token(), Some(Token::InsertBasic(1))));
         assert!(lexer.next_token().is_none());
     }
 
@@ -1053,10 +2178,10 @@ mod tests {
     #[test]
     fn test_mixed_digits_and_commands() {
         let mut lexer = Lexer::new("12x34j");
-        assert!(matches!(
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationBasic(12))
-        ));
+            Some(action(Operator::Delete, 12, Some(Motion::Key('l')), None))
+        );
         assert!(matches!(
             lexer.next_token(),
             Some(Token::MoveVerticalBasic(34))
@@ -1140,21 +2265,21 @@ mod tests {
     #[test]
     fn test_yank_paste() {
         let mut lexer = Lexer::new("pP");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(lexer.next_token(), Some(Token::YankPaste(None))));
+        assert!(matches!(lexer.next_token(), Some(Token::YankPaste(None))));
     }
 
     #[test]
     fn test_text_manipulation_basic_x() {
         let mut lexer = Lexer::new("x5x");
-        assert!(matches!(
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationBasic(1))
-        ));
-        assert!(matches!(
+            Some(action(Operator::Delete, 1, Some(Motion::Key('l')), None))
+        );
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationBasic(5))
-        ));
+            Some(action(Operator::Delete, 5, Some(Motion::Key('l')), None))
+        );
     }
 
     #[test]
@@ -1217,21 +2342,97 @@ mod tests {
     #[test]
     fn test_control_window_management() {
         let mut lexer = Lexer::new("<C-W>s<C-W>v<C-H><C-J><C-K><C-L>");
-        for _ in 0..6 {
-            assert!(matches!(lexer.next_token(), Some(Token::WindowManagement)));
+        assert_eq!(
+            lexer.next_token(),
+            Some(Token::WindowManagement(WindowCommand::Split))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(Token::WindowManagement(WindowCommand::VSplit))
+        );
+        for _ in 0..4 {
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::WindowManagement(WindowCommand::Focus))
+            );
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_window_commands() {
+        let cases = [
+            ("<C-W>w", WindowCommand::Focus),
+            ("<C-W><C-W>", WindowCommand::Focus),
+            ("<C-W><C-V>", WindowCommand::VSplit),
+            ("<C-W>n", WindowCommand::Split),
+            ("<C-W>q", WindowCommand::Close),
+            ("<C-W>c", WindowCommand::Close),
+            ("<C-W>o", WindowCommand::Only),
+            ("<C-W>=", WindowCommand::Equalize),
+            ("<C-W>r", WindowCommand::Rotate),
+            ("<C-W>x", WindowCommand::Rotate),
+            ("<C-W>H", WindowCommand::Move),
+            ("<C-W>T", WindowCommand::Move),
+        ];
+        for (input, expected) in cases {
+            let mut lexer = Lexer::new(input);
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::WindowManagement(expected)),
+                "{input}"
+            );
+            assert!(lexer.next_token().is_none(), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_window_resize_counts() {
+        let cases = [
+            ("<C-W>+", 1),
+            ("5<C-W>-", 5),
+            ("<C-W>10>", 10),
+            ("<C-W><", 1),
+            ("2<C-W>3_", 6),
+            ("<C-W>|", 1),
+        ];
+        for (input, expected) in cases {
+            let mut lexer = Lexer::new(input);
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::WindowManagement(WindowCommand::Resize(expected))),
+                "{input}"
+            );
+            assert!(lexer.next_token().is_none(), "{input}");
         }
     }
 
+    #[test]
+    fn test_window_command_unknown_or_cancelled() {
+        let mut lexer = Lexer::new("<C-W>Zj");
+        assert_eq!(lexer.next_token(), Some(Token::Unhandled("<C-W>Z".into())));
+        assert_eq!(lexer.next_token(), Some(Token::MoveVerticalBasic(1)));
+
+        let mut lexer = Lexer::new("<C-W>|escape|j");
+        assert_eq!(lexer.next_token(), Some(Token::Unhandled("<C-W>".into())));
+        assert_eq!(
+            lexer.next_token(),
+            Some(Token::Unhandled("|escape|".into()))
+        );
+        assert_eq!(lexer.next_token(), Some(Token::MoveVerticalBasic(1)));
+    }
+
     #[test]
     fn test_invalid_control_sequence() {
-        let mut lexer = Lexer::new("<C-X>");
-        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "<C-X>"));
+        let mut lexer = Lexer::new("<C-Z>");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "<C-Z>"));
     }
 
     #[test]
     fn test_incomplete_control_sequence() {
+        // Without a closing '>' the '<' is the unindent operator
         let mut lexer = Lexer::new("<C-");
-        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "<"));
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "<C"));
     }
 
     #[test]
@@ -1283,9 +2484,13 @@ mod tests {
 
     #[test]
     fn test_zz_multiple() {
-        // Two zz commands
-        let mut lexer = Lexer::new("zzzz");
+        // Two zz commands, zzzz would be one zz and its echo
+        let mut lexer = Lexer::new("zzjzz");
         assert!(matches!(lexer.next_token(), Some(Token::CameraMovement)));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalBasic(1))
+        ));
         assert!(matches!(lexer.next_token(), Some(Token::CameraMovement)));
         assert!(lexer.next_token().is_none());
     }
@@ -1307,8 +2512,8 @@ mod tests {
 
     #[test]
     fn test_unrecognized_z_prefix() {
-        let mut lexer = Lexer::new("zx");
-        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "zx"));
+        let mut lexer = Lexer::new("zq");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "zq"));
     }
 
     #[test]
@@ -1366,26 +2571,50 @@ mod tests {
     fn test_delete_line() {
         // dd + 3dd — with Neovim replay: ddd + 3ddd
         let mut lexer = Lexer::new("ddd3ddd");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(3))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(3, None))
+        ));
     }
 
     #[test]
     fn test_delete_motion() {
         // dw, dW, d$, d3w — with Neovim replay
         let mut lexer = Lexer::new("dwwdWWd$$d33w");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(3))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(3, None))
+        ));
     }
 
     #[test]
     fn test_yank() {
         // yw, y$ — with Neovim replay
         let mut lexer = Lexer::new("ywwy$$");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -1394,34 +2623,43 @@ mod tests {
         // cc, cw, c$ — with Neovim replay: ccc + cww + c$$
         let mut lexer = Lexer::new("ccccwwc$$");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
     }
 
     #[test]
     fn test_change_aliases() {
+        // s is cl, S is cc and C is c$
         let mut lexer = Lexer::new("sSC");
-        assert!(matches!(
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationAdvanced)
-        ));
-        assert!(matches!(
+            Some(action(Operator::Change, 1, Some(Motion::Key('l')), None))
+        );
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationAdvanced)
-        ));
-        assert!(matches!(
+            Some(action(Operator::Change, 1, Some(Motion::Line), None))
+        );
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationAdvanced)
-        ));
+            Some(action(Operator::Change, 1, Some(Motion::Key('$')), None))
+        );
+    }
+
+    #[test]
+    fn test_cancelled_operator_and_replace_are_not_operator_actions() {
+        // An escaped operator acts on nothing, r is not an operator at all
+        let mut lexer = Lexer::new("d|escape|r<CR>");
+        assert_eq!(lexer.next_token(), Some(Token::TextManipulationBasic(1)));
+        assert!(lexer.next_token().is_none());
     }
 
     #[test]
@@ -1430,7 +2668,7 @@ mod tests {
         let mut lexer = Lexer::new("ciwiwcawawci)i)ca}a}");
         for _ in 0..4 {
             assert!(matches!(
-                lexer.next_token(),
+                next_legacy(&mut lexer),
                 Some(Token::TextManipulationAdvanced)
             ));
         }
@@ -1441,7 +2679,10 @@ mod tests {
         // diw, daw, di), da} — with Neovim replay
         let mut lexer = Lexer::new("diwiwdawawdi)i)da}a}");
         for _ in 0..4 {
-            assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+            assert!(matches!(
+                next_legacy(&mut lexer),
+                Some(Token::DeleteText(1, None))
+            ));
         }
     }
 
@@ -1450,14 +2691,21 @@ mod tests {
         // yiw, yaw, yi), ya} — with Neovim replay
         let mut lexer = Lexer::new("yiwiwyawawyi)i)ya}a}");
         for _ in 0..4 {
-            assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+            assert!(matches!(
+                next_legacy(&mut lexer),
+                Some(Token::YankPaste(None))
+            ));
         }
     }
 
     #[test]
     fn test_y_uppercase() {
+        // Neovim maps Y to y$
         let mut lexer = Lexer::new("Y");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Yank, 1, Some(Motion::Key('$')), None))
+        );
     }
 
     #[test]
@@ -1482,17 +2730,32 @@ mod tests {
     fn test_delete_with_find_motion() {
         // dfx, dta — with Neovim replay (f/t replayed, target not)
         let mut lexer = Lexer::new("dffxdtta");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
     }
 
     #[test]
     fn test_operator_with_g_motion() {
         // dgg, ygg, ygg — with Neovim replay
         let mut lexer = Lexer::new("dggggyggggygggg");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
     }
 
     #[test]
@@ -1544,7 +2807,7 @@ mod tests {
 
     #[test]
     fn test_generic_command() {
-        let mut lexer = Lexer::new(":Vimscape|enter|:q|escape|");
+        let mut lexer = Lexer::new(":Vimscape|enter|:Lazy|escape|");
         assert!(matches!(lexer.next_token(), Some(Token::Command(true))));
         assert!(matches!(lexer.next_token(), Some(Token::Command(false))));
     }
@@ -1577,7 +2840,7 @@ mod tests {
     fn test_case_toggle() {
         let mut lexer = Lexer::new("g~w");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
     }
@@ -1586,7 +2849,7 @@ mod tests {
     fn test_case_lower() {
         let mut lexer = Lexer::new("guw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
     }
@@ -1595,7 +2858,7 @@ mod tests {
     fn test_case_upper() {
         let mut lexer = Lexer::new("gUw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
     }
@@ -1604,7 +2867,7 @@ mod tests {
     fn test_case_with_count() {
         let mut lexer = Lexer::new("gu3w");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
     }
@@ -1613,7 +2876,7 @@ mod tests {
     fn test_case_with_prefix_count() {
         let mut lexer = Lexer::new("3guw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
     }
@@ -1622,15 +2885,15 @@ mod tests {
     fn test_case_operators_various_motions() {
         let mut lexer = Lexer::new("g~$guegUb");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
     }
@@ -1665,7 +2928,10 @@ mod tests {
             lexer.next_token(),
             Some(Token::CommandSearch(true))
         ));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(matches!(
             lexer.next_token(),
             Some(Token::MoveVerticalBasic(3))
@@ -1691,21 +2957,27 @@ mod tests {
     #[test]
     fn test_delete_to_end_of_line() {
         let mut lexer = Lexer::new("D3D");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(3))));
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Delete, 1, Some(Motion::Key('$')), None))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Delete, 3, Some(Motion::Key('$')), None))
+        );
     }
 
     #[test]
     fn test_delete_char_before() {
         let mut lexer = Lexer::new("X5X");
-        assert!(matches!(
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationBasic(1))
-        ));
-        assert!(matches!(
+            Some(action(Operator::Delete, 1, Some(Motion::Key('h')), None))
+        );
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationBasic(5))
-        ));
+            Some(action(Operator::Delete, 5, Some(Motion::Key('h')), None))
+        );
     }
 
     #[test]
@@ -1785,6 +3057,25 @@ mod tests {
         assert!(matches!(lexer.next_token(), Some(Token::Marks)));
     }
 
+    #[test]
+    fn test_jumplist_navigation() {
+        let mut lexer = Lexer::new("<C-O><C-I>|tab|3<C-O>g;g,2g;''``");
+        for _ in 0..9 {
+            assert!(matches!(lexer.next_token(), Some(Token::JumpList)));
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_mark_jump_vs_jumplist() {
+        let mut lexer = Lexer::new("'a''`a``");
+        assert!(matches!(lexer.next_token(), Some(Token::Marks)));
+        assert!(matches!(lexer.next_token(), Some(Token::JumpList)));
+        assert!(matches!(lexer.next_token(), Some(Token::Marks)));
+        assert!(matches!(lexer.next_token(), Some(Token::JumpList)));
+        assert!(lexer.next_token().is_none());
+    }
+
     #[test]
     fn test_mark_incomplete() {
         let mut lexer = Lexer::new("m");
@@ -1812,82 +3103,85 @@ mod tests {
     #[test]
     fn test_x_raw() {
         let mut lexer = Lexer::new("x");
-        assert!(matches!(
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationBasic(1))
-        ));
+            Some(action(Operator::Delete, 1, Some(Motion::Key('l')), None))
+        );
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_x_with_count_raw() {
         let mut lexer = Lexer::new("5x");
-        assert!(matches!(
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationBasic(5))
-        ));
+            Some(action(Operator::Delete, 5, Some(Motion::Key('l')), None))
+        );
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_s_raw() {
         let mut lexer = Lexer::new("s");
-        assert!(matches!(
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationAdvanced)
-        ));
+            Some(action(Operator::Change, 1, Some(Motion::Key('l')), None))
+        );
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_s_uppercase_raw() {
         let mut lexer = Lexer::new("S");
-        assert!(matches!(
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationAdvanced)
-        ));
+            Some(action(Operator::Change, 1, Some(Motion::Line), None))
+        );
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_x_and_s_mixed_raw() {
         let mut lexer = Lexer::new("xs");
-        assert!(matches!(
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationBasic(1))
-        ));
-        assert!(matches!(
+            Some(action(Operator::Delete, 1, Some(Motion::Key('l')), None))
+        );
+        assert_eq!(
             lexer.next_token(),
-            Some(Token::TextManipulationAdvanced)
-        ));
+            Some(action(Operator::Change, 1, Some(Motion::Key('l')), None))
+        );
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_paste_raw() {
         let mut lexer = Lexer::new("p");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(lexer.next_token(), Some(Token::YankPaste(None))));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_paste_uppercase_raw() {
         let mut lexer = Lexer::new("P");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(lexer.next_token(), Some(Token::YankPaste(None))));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_paste_with_count_raw() {
         let mut lexer = Lexer::new("3p");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(lexer.next_token(), Some(Token::YankPaste(None))));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_yy_raw() {
         let mut lexer = Lexer::new("yy");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -1895,40 +3189,70 @@ mod tests {
     fn test_yy_multiple_raw() {
         // Two yy commands — with Neovim replay: yyy + yyy
         let mut lexer = Lexer::new("yyyyyy");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_yy_with_count_raw() {
         let mut lexer = Lexer::new("3yy");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_delete_word_raw() {
         let mut lexer = Lexer::new("dw");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_delete_end_raw() {
         let mut lexer = Lexer::new("d$");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_delete_various_motions_raw() {
         let mut lexer = Lexer::new("dWdedEdBdB");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -1936,7 +3260,7 @@ mod tests {
     fn test_change_word_raw() {
         let mut lexer = Lexer::new("cw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -1946,7 +3270,7 @@ mod tests {
     fn test_change_end_raw() {
         let mut lexer = Lexer::new("c$");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -1956,11 +3280,11 @@ mod tests {
     fn test_case_operator_raw() {
         let mut lexer = Lexer::new("guwgu$");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -1970,7 +3294,7 @@ mod tests {
     fn test_change_inner_word_raw() {
         let mut lexer = Lexer::new("ciw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -1980,7 +3304,7 @@ mod tests {
     fn test_change_around_word_raw() {
         let mut lexer = Lexer::new("caw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -1989,7 +3313,10 @@ mod tests {
     #[test]
     fn test_delete_inner_word_raw() {
         let mut lexer = Lexer::new("diw");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -1997,7 +3324,7 @@ mod tests {
     fn test_change_inner_paren_raw() {
         let mut lexer = Lexer::new("ci)");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2007,7 +3334,7 @@ mod tests {
     fn test_change_inner_brace_raw() {
         let mut lexer = Lexer::new("ci{");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2017,7 +3344,7 @@ mod tests {
     fn test_change_inner_bracket_raw() {
         let mut lexer = Lexer::new("ci[");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2027,11 +3354,11 @@ mod tests {
     fn test_multiple_text_objects_raw() {
         let mut lexer = Lexer::new("ciwcaw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2068,14 +3395,20 @@ mod tests {
     fn test_dd_replay() {
         // dd arrives as ddd from vim.on_key replay
         let mut lexer = Lexer::new("ddd");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_yy_replay() {
         let mut lexer = Lexer::new("yyy");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2083,7 +3416,7 @@ mod tests {
     fn test_cc_replay() {
         let mut lexer = Lexer::new("ccc");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2095,21 +3428,30 @@ mod tests {
     fn test_d_dollar_replay() {
         // d$ arrives as d$$
         let mut lexer = Lexer::new("d$$");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_y_dollar_replay() {
         let mut lexer = Lexer::new("y$$");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_dw_replay() {
         let mut lexer = Lexer::new("dww");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2117,7 +3459,7 @@ mod tests {
     fn test_cw_replay() {
         let mut lexer = Lexer::new("cww");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2126,7 +3468,10 @@ mod tests {
     #[test]
     fn test_de_replay() {
         let mut lexer = Lexer::new("dee");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2134,7 +3479,10 @@ mod tests {
     fn test_dj_replay() {
         // dj arrives as djj
         let mut lexer = Lexer::new("djj");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2145,7 +3493,7 @@ mod tests {
         // ciw arrives as ciwiw
         let mut lexer = Lexer::new("ciwiw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2154,14 +3502,20 @@ mod tests {
     #[test]
     fn test_diw_replay() {
         let mut lexer = Lexer::new("diwiw");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_yiw_replay() {
         let mut lexer = Lexer::new("yiwiw");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2169,7 +3523,7 @@ mod tests {
     fn test_caw_replay() {
         let mut lexer = Lexer::new("cawaw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2180,7 +3534,7 @@ mod tests {
         // ci) arrives as ci)i)
         let mut lexer = Lexer::new("ci)i)");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2192,14 +3546,20 @@ mod tests {
     fn test_dfa_replay() {
         // dfa arrives as dffa (f is replayed, target a is not)
         let mut lexer = Lexer::new("dffa");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_dta_replay() {
         let mut lexer = Lexer::new("dtta");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2207,7 +3567,7 @@ mod tests {
     fn test_cfa_replay() {
         let mut lexer = Lexer::new("cffa");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2219,14 +3579,20 @@ mod tests {
     fn test_dgg_replay() {
         // dgg arrives as dgggg (full gg motion replayed)
         let mut lexer = Lexer::new("dgggg");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_ygg_replay() {
         let mut lexer = Lexer::new("ygggg");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2234,7 +3600,10 @@ mod tests {
     fn test_dgj_replay() {
         // dgj arrives as dgjgj
         let mut lexer = Lexer::new("dgjgj");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2244,7 +3613,10 @@ mod tests {
     fn test_d3w_replay() {
         // d3w arrives as d33w (count digit replayed, motion char not)
         let mut lexer = Lexer::new("d33w");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(3))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(3, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2252,7 +3624,10 @@ mod tests {
     fn test_d3d_replay() {
         // d3d arrives as d33d (count replayed, doubled-op d skipped)
         let mut lexer = Lexer::new("d33d");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(3))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(3, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2260,7 +3635,10 @@ mod tests {
     fn test_prefix_count_dw_replay() {
         // 3dw arrives as 3dww (prefix count outside operator-pending, motion replayed)
         let mut lexer = Lexer::new("3dww");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(3))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(3, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2271,7 +3649,7 @@ mod tests {
         // g~w arrives as g~ww
         let mut lexer = Lexer::new("g~ww");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2282,7 +3660,7 @@ mod tests {
         // gue arrives as guee
         let mut lexer = Lexer::new("guee");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2293,7 +3671,7 @@ mod tests {
         // gU$ arrives as gU$$
         let mut lexer = Lexer::new("gU$$");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2304,7 +3682,7 @@ mod tests {
         // guiw arrives as guiwiw
         let mut lexer = Lexer::new("guiwiw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2316,14 +3694,20 @@ mod tests {
     #[test]
     fn test_no_replay_dd() {
         let mut lexer = Lexer::new("dd");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_no_replay_dw() {
         let mut lexer = Lexer::new("dw");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2331,7 +3715,7 @@ mod tests {
     fn test_no_replay_ciw() {
         let mut lexer = Lexer::new("ciw");
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(lexer.next_token().is_none());
@@ -2340,14 +3724,20 @@ mod tests {
     #[test]
     fn test_no_replay_dfa() {
         let mut lexer = Lexer::new("dfa");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
     #[test]
     fn test_no_replay_dgg() {
         let mut lexer = Lexer::new("dgg");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2357,15 +3747,27 @@ mod tests {
     fn test_real_batch_replay_1() {
         // From actual Neovim session: $, 0, d$, u, j, y$, :Vimscape flush<enter>
         let mut lexer = Lexer::new("$0d$$ujy$$:Vimscape|space|flush|enter|");
-        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "$"));
-        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "0"));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::JumpToLinePosition(1))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::JumpToLinePosition(1))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(matches!(lexer.next_token(), Some(Token::UndoRedo)));
         assert!(matches!(
             lexer.next_token(),
             Some(Token::MoveVerticalBasic(1))
         ));
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
         assert!(matches!(lexer.next_token(), Some(Token::Command(true))));
         assert!(lexer.next_token().is_none());
     }
@@ -2374,10 +3776,13 @@ mod tests {
     fn test_real_batch_replay_2() {
         // From actual Neovim session: dd, u, ciw, u, :w<enter>
         let mut lexer = Lexer::new("ddduciwiwu:w|enter|");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(matches!(lexer.next_token(), Some(Token::UndoRedo)));
         assert!(matches!(
-            lexer.next_token(),
+            next_legacy(&mut lexer),
             Some(Token::TextManipulationAdvanced)
         ));
         assert!(matches!(lexer.next_token(), Some(Token::UndoRedo)));
@@ -2390,9 +3795,18 @@ mod tests {
         // From actual Neovim session: yiw, 3dw, d3w
         // yiw→yiwiw, 3dw→3dww, d3w→d33w
         let mut lexer = Lexer::new("yiwiw3dwwd33w");
-        assert!(matches!(lexer.next_token(), Some(Token::YankPaste)));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(3))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(3))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(3, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(3, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2413,8 +3827,14 @@ mod tests {
             lexer.next_token(),
             Some(Token::MoveVerticalBasic(1))
         ));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(lexer.next_token().is_none());
     }
 
@@ -2425,7 +3845,10 @@ mod tests {
         // User types dw then w. Arrives as dww + w = dwww
         // Lexer: dw (consume, skip dup w) → DeleteText, then w → MoveHorizontalChunk
         let mut lexer = Lexer::new("dwww");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
         assert!(matches!(
             lexer.next_token(),
             Some(Token::MoveHorizontalChunk(1))
@@ -2438,8 +3861,1802 @@ mod tests {
         // User types dd then d$. Arrives as ddd + d$$ = dddd$$
         // Lexer: dd (skip dup d) → DeleteText, d$ (skip dup $) → DeleteText
         let mut lexer = Lexer::new("dddd$$");
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
-        assert!(matches!(lexer.next_token(), Some(Token::DeleteText(1))));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    // =========================================================================
+    // Visual Mode Tests
+    // =========================================================================
+
+    #[test]
+    fn test_visual_delete() {
+        // v, select two words, delete
+        let mut lexer = Lexer::new("vwwd");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualManipulation(2, None))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_line_yank() {
+        let mut lexer = Lexer::new("V3jy");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualYank(1, None))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_block_insert() {
+        let mut lexer = Lexer::new("<C-V>jjI");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualManipulation(2, None))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_text_object() {
+        // viw then change case, vi( then change
+        let mut lexer = Lexer::new("viwUvi(c");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualManipulation(1, None))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualManipulation(1, None))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_operators() {
+        let mut lexer = Lexer::new("vd vy vc v> v< v~ vu vU vJ vA vx vrx");
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token() {
+            tokens.push(token);
+        }
+        let manipulations = tokens
+            .iter()
+            .filter(|t| matches!(t, Token::VisualManipulation(0, None)))
+            .count();
+        let yanks = tokens
+            .iter()
+            .filter(|t| matches!(t, Token::VisualYank(0, None)))
+            .count();
+        assert_eq!(manipulations, 11);
+        assert_eq!(yanks, 1);
+    }
+
+    #[test]
+    fn test_visual_escape() {
+        let mut lexer = Lexer::new("vjj|escape|j");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualSelection(2))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalBasic(1))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_toggle_off() {
+        // vv and VV both leave visual mode without an operator
+        let mut lexer = Lexer::new("vlvVjVx");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualSelection(1))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualSelection(1))
+        ));
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Delete, 1, Some(Motion::Key('l')), None))
+        );
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_line_keys_end_selection() {
+        // Y, p and P are clipboard operators; D, X, C, S and R rewrite lines
+        for (input, yank) in [
+            ("vjYjj", true),
+            ("vjpjj", true),
+            ("vjPjj", true),
+            ("vjDjj", false),
+            ("vjXjj", false),
+            ("vjCjj", false),
+            ("vjSjj", false),
+            ("vjRjj", false),
+        ] {
+            let mut lexer = Lexer::new(input);
+            let expected = if yank {
+                Token::VisualYank(1, None)
+            } else {
+                Token::VisualManipulation(1, None)
+            };
+            assert_eq!(lexer.next_token(), Some(expected), "{input}");
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::MoveVerticalBasic(1)),
+                "{input}"
+            );
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::MoveVerticalBasic(1)),
+                "{input}"
+            );
+            assert!(lexer.next_token().is_none(), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_visual_g_operators_end_selection() {
+        for input in ["vjguj", "vjgUj", "vjg~j", "vjgJj"] {
+            let mut lexer = Lexer::new(input);
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::VisualManipulation(1, None)),
+                "{input}"
+            );
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::MoveVerticalBasic(1)),
+                "{input}"
+            );
+            assert!(lexer.next_token().is_none(), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_visual_enter_and_ctrl_c_end_selection() {
+        for input in ["vj|enter|jj", "vj<C-C>jj"] {
+            let mut lexer = Lexer::new(input);
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::VisualSelection(1)),
+                "{input}"
+            );
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::MoveVerticalBasic(1)),
+                "{input}"
+            );
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::MoveVerticalBasic(1)),
+                "{input}"
+            );
+            assert!(lexer.next_token().is_none(), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_visual_paste_then_delete_line() {
+        let mut lexer = Lexer::new("viwpdd");
+        assert_eq!(lexer.next_token(), Some(Token::VisualYank(1, None)));
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Delete, 1, Some(Motion::Line), None))
+        );
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_line_yank_keeps_register() {
+        let mut lexer = Lexer::new("V\"aY");
+        assert_eq!(lexer.next_token(), Some(Token::VisualYank(0, Some('a'))));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_switch_kind() {
+        // v then V switches to linewise instead of leaving visual mode
+        let mut lexer = Lexer::new("vVjd");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualManipulation(1, None))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_find_and_g_motions() {
+        let mut lexer = Lexer::new("vfxggy");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualYank(2, None))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_command() {
+        // :'<,'> is inserted by Neovim, only the typed keys arrive
+        let mut lexer = Lexer::new("Vjj:sort|enter|");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualSelection(2))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::ExCommand(
+                ExCommand::Sort,
+                Some(ExRange::Span(
+                    ExAddress {
+                        kind: ExAddressKind::Mark('<'),
+                        offset: 0
+                    },
+                    ExAddress {
+                        kind: ExAddressKind::Mark('>'),
+                        offset: 0
+                    }
+                )),
+                true
+            ))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_incomplete() {
+        let mut lexer = Lexer::new("vjj");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualSelection(2))
+        ));
         assert!(lexer.next_token().is_none());
     }
+
+    // =========================================================================
+    // Macro Tests
+    // =========================================================================
+
+    #[test]
+    fn test_macro_record_and_stop() {
+        let mut lexer = Lexer::new("qajjq");
+        assert!(matches!(lexer.next_token(), Some(Token::MacroRecordStart)));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalBasic(1))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalBasic(1))
+        ));
+        assert!(matches!(lexer.next_token(), Some(Token::MacroRecordStop)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_macro_record_register_not_lexed_as_command() {
+        // The register after q is not a motion, and q after recording stops it
+        let mut lexer = Lexer::new("qjxqqk");
+        assert!(matches!(lexer.next_token(), Some(Token::MacroRecordStart)));
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Delete, 1, Some(Motion::Key('l')), None))
+        );
+        assert!(matches!(lexer.next_token(), Some(Token::MacroRecordStop)));
+        assert!(matches!(lexer.next_token(), Some(Token::MacroRecordStart)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_macro_playback() {
+        let mut lexer = Lexer::new("@a@@10@b");
+        assert!(matches!(lexer.next_token(), Some(Token::MacroPlayback(1))));
+        assert!(matches!(lexer.next_token(), Some(Token::MacroPlayback(1))));
+        assert!(matches!(lexer.next_token(), Some(Token::MacroPlayback(10))));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_macro_playback_last_command() {
+        let mut lexer = Lexer::new("@:");
+        assert!(matches!(lexer.next_token(), Some(Token::MacroPlayback(1))));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_macro_command_window_unhandled() {
+        let mut lexer = Lexer::new("q:");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "q:"));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_macro_incomplete() {
+        let mut lexer = Lexer::new("q");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "q"));
+        let mut lexer = Lexer::new("3@");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "@"));
+    }
+
+    // =========================================================================
+    // Register Prefix Tests
+    // =========================================================================
+
+    #[test]
+    fn test_register_yank_line() {
+        let mut lexer = Lexer::new("\"ayy");
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(Some('a')))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_register_paste_not_append() {
+        // "ap is a paste from register a, not an append
+        let mut lexer = Lexer::new("\"ap\"+P");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::YankPaste(Some('a')))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::YankPaste(Some('+')))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_register_black_hole_delete() {
+        let mut lexer = Lexer::new("\"_ddd\"_diwiw");
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, Some('_')))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, Some('_')))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_register_with_count_after() {
+        let mut lexer = Lexer::new("\"a3ddd");
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(3, Some('a')))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_register_with_count_before() {
+        let mut lexer = Lexer::new("3\"byy");
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::YankPaste(Some('b')))
+        ));
+        let mut lexer = Lexer::new("2\"*D");
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(2, Some('*')))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_register_on_operator_alias() {
+        let mut lexer = Lexer::new("\"aD\"+x");
+        let Some(Token::Operator(delete_to_end)) = lexer.next_token() else {
+            panic!("D should be an operator action");
+        };
+        assert_eq!(delete_to_end.motion, Some(Motion::Key('$')));
+        assert_eq!(delete_to_end.register, Some('a'));
+        let Some(Token::Operator(delete_char)) = lexer.next_token() else {
+            panic!("x should be an operator action");
+        };
+        assert_eq!(delete_char.register, Some('+'));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_register_ignored_by_other_commands() {
+        let mut lexer = Lexer::new("\"aJj");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::TextManipulationBasic(1))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalBasic(1))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_register_in_visual_mode() {
+        let mut lexer = Lexer::new("viw\"+y");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualYank(1, Some('+')))
+        ));
+        assert!(lexer.next_token().is_none());
+
+        let mut lexer = Lexer::new("v\"adVj\"_x");
+        assert_eq!(
+            lexer.next_token(),
+            Some(Token::VisualManipulation(0, Some('a')))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(Token::VisualManipulation(1, Some('_')))
+        );
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_register_incomplete() {
+        let mut lexer = Lexer::new("\"");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "\""));
+        let mut lexer = Lexer::new("\"a");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "\"a"));
+        assert!(lexer.next_token().is_none());
+    }
+
+    // =========================================================================
+    // Anti-Pattern Tests
+    // =========================================================================
+
+    #[test]
+    fn test_arrow_keys() {
+        let mut lexer = Lexer::new("|up||down||left||right|");
+        for _ in 0..4 {
+            assert!(matches!(
+                lexer.next_token(),
+                Some(Token::AntiPattern(AntiPattern::ArrowKey))
+            ));
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_page_home_end_and_mouse_keys() {
+        let mut lexer = Lexer::new("|pageup||pagedown||home||end||mouse|");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::AntiPattern(AntiPattern::PageKey))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::AntiPattern(AntiPattern::PageKey))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::AntiPattern(AntiPattern::HomeEndKey))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::AntiPattern(AntiPattern::HomeEndKey))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::AntiPattern(AntiPattern::Mouse))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_arrow_keys_in_command_line_ignored() {
+        // History navigation inside the command line is not an anti-pattern
+        let mut lexer = Lexer::new(":|up||up||enter|");
+        assert!(matches!(lexer.next_token(), Some(Token::Command(true))));
+        assert!(lexer.next_token().is_none());
+    }
+
+    // =========================================================================
+    // Line Position Tests
+    // =========================================================================
+
+    #[test]
+    fn test_line_position_motions() {
+        let mut lexer = Lexer::new("0^$g_g0g$gm");
+        for _ in 0..7 {
+            assert!(matches!(
+                lexer.next_token(),
+                Some(Token::JumpToLinePosition(1))
+            ));
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_line_position_with_count() {
+        let mut lexer = Lexer::new("3$2g_5|4^");
+        assert_eq!(lexer.next_token(), Some(Token::JumpToLinePosition(3)));
+        assert_eq!(lexer.next_token(), Some(Token::JumpToLinePosition(2)));
+        assert_eq!(lexer.next_token(), Some(Token::JumpToLinePosition(5)));
+        // ^ ignores its count
+        assert_eq!(lexer.next_token(), Some(Token::JumpToLinePosition(1)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_zero_inside_count_is_not_a_motion() {
+        let mut lexer = Lexer::new("10j");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalBasic(10))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_column_motion_vs_pipe_keys() {
+        // A bare '|' is a motion, but |up| is still an arrow key
+        let mut lexer = Lexer::new("|j|up|");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::JumpToLinePosition(1))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalBasic(1))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::AntiPattern(AntiPattern::ArrowKey))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_count_before_pipe_key() {
+        let mut lexer = Lexer::new("5|up|");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "5"));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::AntiPattern(AntiPattern::ArrowKey))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_operator_with_g_underscore() {
+        let mut lexer = Lexer::new("dg_g_");
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::DeleteText(1, None))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    // =========================================================================
+    // Fold Tests
+    // =========================================================================
+
+    #[test]
+    fn test_fold_toggles() {
+        let mut lexer = Lexer::new("zozcza zRzMzv");
+        for _ in 0..3 {
+            assert!(matches!(lexer.next_token(), Some(Token::FoldToggle)));
+        }
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == " "));
+        for _ in 0..3 {
+            assert!(matches!(lexer.next_token(), Some(Token::FoldToggle)));
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_fold_move_with_count() {
+        let mut lexer = Lexer::new("zj3zk");
+        assert!(matches!(lexer.next_token(), Some(Token::FoldMove(1))));
+        assert!(matches!(lexer.next_token(), Some(Token::FoldMove(3))));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_fold_create_operator() {
+        let mut lexer = Lexer::new("zfjjzfapzf3jzF");
+        for _ in 0..4 {
+            assert!(matches!(next_legacy(&mut lexer), Some(Token::FoldCreate)));
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_fold_create_incomplete() {
+        let mut lexer = Lexer::new("zf");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "zf"));
+        let mut lexer = Lexer::new("zfzf");
+        assert!(matches!(lexer.next_token(), Some(Token::Unhandled(ref s)) if s == "zfz"));
+    }
+
+    #[test]
+    fn test_fold_delete() {
+        let mut lexer = Lexer::new("zdzE");
+        assert!(matches!(lexer.next_token(), Some(Token::FoldDelete)));
+        assert!(matches!(lexer.next_token(), Some(Token::FoldDelete)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_fold_create() {
+        let mut lexer = Lexer::new("Vjjzf");
+        assert!(matches!(lexer.next_token(), Some(Token::FoldCreate)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    // =========================================================================
+    // Indent, Format and Filter Operator Tests
+    // =========================================================================
+
+    #[test]
+    fn test_indent_doubled_with_count() {
+        let mut lexer = Lexer::new(">>3<<");
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::IndentText(1))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::IndentText(3))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_indent_with_motion_and_text_object() {
+        let mut lexer = Lexer::new(">G<ip>2j");
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::IndentText(1))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::IndentText(1))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::IndentText(2))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_unindent_does_not_break_control_sequences() {
+        let mut lexer = Lexer::new("<C-D><<");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalChunk(1))
+        ));
+        assert!(matches!(
+            next_legacy(&mut lexer),
+            Some(Token::IndentText(1))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_reindent_and_format() {
+        for input in [
+            "==", "=ip", "=G", "gqq", "gqgq", "gqap", "gwip", "gww", "gwgw", "3gww",
+        ] {
+            let mut lexer = Lexer::new(input);
+            assert!(
+                matches!(next_legacy(&mut lexer), Some(Token::FormatText)),
+                "{input} should be FormatText"
+            );
+            assert!(lexer.next_token().is_none());
+        }
+    }
+
+    #[test]
+    fn test_rot13() {
+        let mut lexer = Lexer::new("g??g?g?g?w");
+        for _ in 0..3 {
+            assert!(matches!(
+                next_legacy(&mut lexer),
+                Some(Token::TextManipulationAdvanced)
+            ));
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_filter_consumes_command_line() {
+        let mut lexer = Lexer::new("!ipsort|space|-u|enter|j");
+        assert!(matches!(next_legacy(&mut lexer), Some(Token::FilterText)));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalBasic(1))
+        ));
+        assert!(lexer.next_token().is_none());
+
+        let mut lexer = Lexer::new("!!jq|escape|");
+        assert!(matches!(next_legacy(&mut lexer), Some(Token::FilterText)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_format_and_filter() {
+        let mut lexer = Lexer::new("Vj=vipgqVj!sort|enter|");
+        for _ in 0..3 {
+            assert!(matches!(
+                lexer.next_token(),
+                Some(Token::VisualManipulation(_, None))
+            ));
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    // =========================================================================
+    // Insert Entry Tests
+    // =========================================================================
+
+    #[test]
+    fn test_insert_basic() {
+        let mut lexer = Lexer::new("ia3igI");
+        assert!(matches!(lexer.next_token(), Some(Token::InsertBasic(1))));
+        assert!(matches!(lexer.next_token(), Some(Token::InsertBasic(1))));
+        assert!(matches!(lexer.next_token(), Some(Token::InsertBasic(3))));
+        assert!(matches!(lexer.next_token(), Some(Token::InsertBasic(1))));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_insert_advanced() {
+        let mut lexer = Lexer::new("IAoO3ogi");
+        for _ in 0..4 {
+            assert!(matches!(lexer.next_token(), Some(Token::InsertAdvanced(1))));
+        }
+        assert!(matches!(lexer.next_token(), Some(Token::InsertAdvanced(3))));
+        assert!(matches!(lexer.next_token(), Some(Token::InsertAdvanced(1))));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_insert_followed_by_normal_command() {
+        // The typed text and <Esc> are dropped, so the next key is a new command
+        let mut lexer = Lexer::new("oj");
+        assert!(matches!(lexer.next_token(), Some(Token::InsertAdvanced(1))));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::MoveVerticalBasic(1))
+        ));
+        assert!(lexer.next_token().is_none());
+    }
+
+    // =========================================================================
+    // Word Search and Goto Definition Tests
+    // =========================================================================
+
+    #[test]
+    fn test_search_word_under_cursor() {
+        let mut lexer = Lexer::new("*#g*g#");
+        for _ in 0..4 {
+            assert!(matches!(lexer.next_token(), Some(Token::SearchWord(1))));
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_search_word_with_count() {
+        let mut lexer = Lexer::new("3*2g#");
+        assert!(matches!(lexer.next_token(), Some(Token::SearchWord(3))));
+        assert!(matches!(lexer.next_token(), Some(Token::SearchWord(2))));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_goto_definition() {
+        let mut lexer = Lexer::new("gdgD");
+        assert!(matches!(lexer.next_token(), Some(Token::GotoDefinition)));
+        assert!(matches!(lexer.next_token(), Some(Token::GotoDefinition)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    // =========================================================================
+    // Ex Command Classification Tests
+    // =========================================================================
+
+    fn lex_command(input: &str) -> Option<Token> {
+        Lexer::new(input).next_token()
+    }
+
+    #[test]
+    fn test_ex_substitute_and_global() {
+        for input in [
+            ":s/foo/bar/|enter|",
+            ":%s/foo/bar/g|enter|",
+            ":'<,'>s/a/b/|enter|",
+            ":.,$substitute/a/b/|enter|",
+        ] {
+            assert!(
+                matches!(
+                    lex_command(input),
+                    Some(Token::ExCommand(ExCommand::Substitute, _, true))
+                ),
+                "{input}"
+            );
+        }
+        for input in [":g/TODO/d|enter|", ":g!/x/d|enter|", ":v/^$/d|enter|"] {
+            assert_eq!(
+                lex_command(input),
+                Some(Token::ExCommand(ExCommand::Global, None, true)),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_ex_abbreviations() {
+        let cases = [
+            (":norm|space|A;|enter|", ExCommand::Normal),
+            (":normal!|space|dd|enter|", ExCommand::Normal),
+            (":sor|enter|", ExCommand::Sort),
+            (":e|space|main.rs|enter|", ExCommand::Edit),
+            (":q|enter|", ExCommand::Quit),
+            (":qa!|enter|", ExCommand::Quit),
+            (":wq|enter|", ExCommand::WriteQuit),
+            (":x|enter|", ExCommand::WriteQuit),
+            (":sp|enter|", ExCommand::Split),
+            (":vs|enter|", ExCommand::Split),
+            (":bn|enter|", ExCommand::Buffer),
+            (":bd|enter|", ExCommand::Buffer),
+            (":cn|enter|", ExCommand::Quickfix),
+            (":make|enter|", ExCommand::Make),
+            (":b|space|lexer|enter|", ExCommand::Buffer),
+            (":ls|enter|", ExCommand::Buffer),
+            (":tabnew|enter|", ExCommand::Tab),
+            (":tabn|enter|", ExCommand::Tab),
+            (":tabc|enter|", ExCommand::Tab),
+            (":args|enter|", ExCommand::Args),
+            (":n|enter|", ExCommand::Args),
+            (":N|enter|", ExCommand::Args),
+            (":prev|enter|", ExCommand::Args),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                lex_command(input),
+                Some(Token::ExCommand(expected, None, true)),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_ex_new_is_not_next() {
+        assert_eq!(
+            lex_command(":new|enter|"),
+            Some(Token::ExCommand(ExCommand::Split, None, true))
+        );
+        assert_eq!(
+            lex_command(":ne|enter|"),
+            Some(Token::ExCommand(ExCommand::Args, None, true))
+        );
+    }
+
+    #[test]
+    fn test_buffer_and_tab_switching() {
+        let mut lexer = Lexer::new("gtgT3gt<C-^><C-6>");
+        for _ in 0..5 {
+            assert_eq!(lexer.next_token(), Some(Token::BufferSwitch));
+        }
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_ex_too_short_abbreviation_is_generic() {
+        // :no is :noremap, not :normal, and :so is :source, not :sort
+        assert_eq!(lex_command(":no|enter|"), Some(Token::Command(true)));
+        assert_eq!(lex_command(":so|enter|"), Some(Token::Command(true)));
+    }
+
+    #[test]
+    fn test_ex_command_cancelled() {
+        assert_eq!(
+            lex_command(":%s/a/b|escape|"),
+            Some(Token::ExCommand(
+                ExCommand::Substitute,
+                Some(ExRange::WholeFile),
+                false
+            ))
+        );
+    }
+
+    #[test]
+    fn test_ex_write_forms_still_save() {
+        assert_eq!(lex_command(":w!|enter|"), Some(Token::SaveFile(true)));
+        assert_eq!(lex_command(":write|enter|"), Some(Token::SaveFile(true)));
+    }
+
+    // =========================================================================
+    // Ex Range Tests
+    // =========================================================================
+
+    fn line(n: u32) -> ExAddress {
+        ExAddress {
+            kind: ExAddressKind::Line(n),
+            offset: 0,
+        }
+    }
+
+    #[test]
+    fn test_ex_range_numbers() {
+        assert_eq!(
+            lex_command(":1,10d|enter|"),
+            Some(Token::ExCommand(
+                ExCommand::Delete,
+                Some(ExRange::Span(line(1), line(10))),
+                true
+            ))
+        );
+    }
+
+    #[test]
+    fn test_ex_range_current_last_and_offsets() {
+        assert_eq!(
+            Lexer::parse_ex_range(".,$s/a/b/"),
+            (
+                Some(ExRange::Span(
+                    ExAddress {
+                        kind: ExAddressKind::Current,
+                        offset: 0
+                    },
+                    ExAddress {
+                        kind: ExAddressKind::Last,
+                        offset: 0
+                    }
+                )),
+                "s/a/b/"
+            )
+        );
+        assert_eq!(
+            Lexer::parse_ex_range(".+2;+3-1m0"),
+            (
+                Some(ExRange::Span(
+                    ExAddress {
+                        kind: ExAddressKind::Current,
+                        offset: 2
+                    },
+                    ExAddress {
+                        kind: ExAddressKind::Current,
+                        offset: 2
+                    }
+                )),
+                "m0"
+            )
+        );
+    }
+
+    #[test]
+    fn test_ex_range_patterns_and_marks() {
+        assert_eq!(
+            Lexer::parse_ex_range("/fn main/,'b y"),
+            (
+                Some(ExRange::Span(
+                    ExAddress {
+                        kind: ExAddressKind::Pattern("fn main".to_string()),
+                        offset: 0
+                    },
+                    ExAddress {
+                        kind: ExAddressKind::Mark('b'),
+                        offset: 0
+                    }
+                )),
+                "y"
+            )
+        );
+        assert_eq!(
+            Lexer::parse_ex_range("?a\\?b?"),
+            (
+                Some(ExRange::Single(ExAddress {
+                    kind: ExAddressKind::Pattern("a\\?b".to_string()),
+                    offset: 0
+                })),
+                ""
+            )
+        );
+    }
+
+    #[test]
+    fn test_ex_range_without_command_is_a_jump() {
+        assert!(matches!(
+            lex_command(":42|enter|"),
+            Some(Token::JumpToLineNumber(ref s)) if s == "42"
+        ));
+        assert!(matches!(
+            lex_command(":$|enter|"),
+            Some(Token::JumpToLineNumber(ref s)) if s == "$"
+        ));
+        assert!(matches!(
+            lex_command(":'a|enter|"),
+            Some(Token::JumpToLineNumber(ref s)) if s == "'a"
+        ));
+    }
+
+    #[test]
+    fn test_cancelled_ex_range_is_not_a_jump() {
+        assert_eq!(lex_command(":42|escape|"), Some(Token::Command(false)));
+    }
+
+    #[test]
+    fn test_cancelled_visual_command_is_not_a_jump() {
+        let mut lexer = Lexer::new("vj:|escape|");
+        assert_eq!(lexer.next_token(), Some(Token::VisualSelection(1)));
+        assert_eq!(lexer.next_token(), Some(Token::Command(false)));
+        assert!(lexer.next_token().is_none());
+
+        // Enter on the bare range runs nothing worth a jump either
+        let mut lexer = Lexer::new("V:|enter|");
+        assert_eq!(lexer.next_token(), Some(Token::VisualSelection(0)));
+        assert_eq!(lexer.next_token(), Some(Token::Command(true)));
+    }
+
+    #[test]
+    fn test_ex_range_with_symbol_command_is_generic() {
+        assert_eq!(lex_command(":10>|enter|"), Some(Token::Command(true)));
+    }
+
+    #[test]
+    fn test_visual_command_carries_selection_range() {
+        let mut lexer = Lexer::new("vj:d|enter|");
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::VisualSelection(1))
+        ));
+        assert!(matches!(
+            lexer.next_token(),
+            Some(Token::ExCommand(
+                ExCommand::Delete,
+                Some(ExRange::Span(_, _)),
+                true
+            ))
+        ));
+    }
+
+    // =========================================================================
+    // Batch Carryover Tests
+    // =========================================================================
+
+    /// Lex each batch the way `process_batch` does, then flush after the last
+    fn lex_batches(batches: &[&str]) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut checkpoint = Checkpoint::default();
+        for (i, batch) in batches.iter().enumerate() {
+            let input = format!("{}{batch}", checkpoint.pending());
+            let mut lexer = Lexer::resume(&input, checkpoint, i + 1 < batches.len());
+            while let Some(token) = lexer.next_token() {
+                tokens.push(token);
+            }
+            checkpoint = lexer.checkpoint();
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_operator_split_across_batches() {
+        assert_eq!(
+            lex_batches(&["jd", "w"]),
+            vec![
+                Token::MoveVerticalBasic(1),
+                action(Operator::Delete, 1, Some(Motion::Key('w')), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_count_split_across_batches() {
+        assert_eq!(
+            lex_batches(&["1", "2j"]),
+            vec![Token::MoveVerticalBasic(12)]
+        );
+    }
+
+    #[test]
+    fn test_command_split_across_batches() {
+        assert_eq!(
+            lex_batches(&[":%s/foo", "/bar/|enter|"]),
+            vec![Token::ExCommand(
+                ExCommand::Substitute,
+                Some(ExRange::WholeFile),
+                true
+            )]
+        );
+        assert_eq!(
+            lex_batches(&["/needle", "|enter|"]),
+            vec![Token::CommandSearch(true)]
+        );
+    }
+
+    #[test]
+    fn test_state_after_token_carries_over() {
+        // The visual ':' leaves the lexer in command mode with no keys pending
+        let tokens = lex_batches(&["vj:", "d|enter|"]);
+        assert_eq!(tokens[0], Token::VisualSelection(1));
+        assert!(matches!(
+            tokens[1],
+            Token::ExCommand(ExCommand::Delete, Some(ExRange::Span(_, _)), true)
+        ));
+        assert_eq!(tokens.len(), 2);
+    }
+
+    #[test]
+    fn test_macro_recording_carries_over() {
+        assert_eq!(
+            lex_batches(&["qa", "jq"]),
+            vec![
+                Token::MacroRecordStart,
+                Token::MoveVerticalBasic(1),
+                Token::MacroRecordStop
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flush_emits_incomplete_command() {
+        assert_eq!(lex_batches(&["d"]), vec![Token::Unhandled("d".into())]);
+        assert_eq!(
+            lex_batches(&["j", "d"]),
+            vec![Token::MoveVerticalBasic(1), Token::Unhandled("d".into())]
+        );
+    }
+
+    #[test]
+    fn test_long_unfinished_command_is_not_carried() {
+        let long_command = format!(":{}", "a".repeat(MAX_PENDING_LEN));
+        let mut lexer = Lexer::resume(&long_command, Checkpoint::default(), true);
+        assert_eq!(lexer.next_token(), Some(Token::Command(false)));
+        assert!(lexer.next_token().is_none());
+        assert!(lexer.checkpoint().pending().is_empty());
+    }
+
+    // =========================================================================
+    // Span Tests
+    // =========================================================================
+
+    fn spans(input: &str) -> Vec<(Token, &str)> {
+        let mut lexer = Lexer::new(input);
+        let mut spanned = Vec::new();
+        while let Some(token) = lexer.next_token_with_span() {
+            let raw = token.raw(input);
+            spanned.push((token.token, raw));
+        }
+        spanned
+    }
+
+    #[test]
+    fn test_spans_cover_raw_keys() {
+        assert_eq!(
+            spans("5jdiw:w|enter|"),
+            vec![
+                (Token::MoveVerticalBasic(5), "5j"),
+                (action(Operator::Delete, 1, None, Some(inner('w'))), "diw"),
+                (Token::SaveFile(true), ":w|enter|"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans_include_replayed_keys() {
+        // The echoed 'd' of dd belongs to the same token
+        assert_eq!(
+            spans("dddj"),
+            vec![
+                (action(Operator::Delete, 1, Some(Motion::Line), None), "ddd"),
+                (Token::MoveVerticalBasic(1), "j"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans_are_byte_offsets() {
+        let mut lexer = Lexer::new("fé|escape|");
+        let spanned = lexer.next_token_with_span().unwrap();
+        assert_eq!(spanned.token, Token::JumpToHorizontal);
+        assert_eq!(spanned.span, 0..3);
+    }
+
+    // ===========================================
+    // Structured operator actions
+    // ===========================================
+
+    #[test]
+    fn test_operator_actions_keep_motion_and_text_object() {
+        let mut lexer = Lexer::new("ciwc$5dd");
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Change, 1, None, Some(inner('w'))))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Change, 1, Some(Motion::Key('$')), None))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Delete, 5, Some(Motion::Line), None))
+        );
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_operator_action_motions() {
+        let cases = [
+            (
+                "2d3w",
+                action(Operator::Delete, 6, Some(Motion::Key('w')), None),
+            ),
+            (
+                "dfa",
+                action(Operator::Delete, 1, Some(Motion::Find('f', 'a')), None),
+            ),
+            ("ygg", action(Operator::Yank, 1, Some(Motion::G('g')), None)),
+            (
+                "gqgq",
+                action(Operator::Format, 1, Some(Motion::Line), None),
+            ),
+            (
+                "gwgw",
+                action(Operator::FormatKeep, 1, Some(Motion::Line), None),
+            ),
+            (
+                "2gww",
+                action(Operator::FormatKeep, 2, Some(Motion::Line), None),
+            ),
+            (
+                "gwap",
+                action(
+                    Operator::FormatKeep,
+                    1,
+                    None,
+                    Some(TextObject {
+                        around: true,
+                        object: 'p',
+                    }),
+                ),
+            ),
+            (
+                "da)",
+                action(
+                    Operator::Delete,
+                    1,
+                    None,
+                    Some(TextObject {
+                        around: true,
+                        object: ')',
+                    }),
+                ),
+            ),
+        ];
+        for (input, expected) in cases {
+            let mut lexer = Lexer::new(input);
+            assert_eq!(lexer.next_token(), Some(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_case_operators_are_operator_actions() {
+        let mut lexer = Lexer::new("gUiwguugugu");
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Uppercase, 1, None, Some(inner('w'))))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Lowercase, 1, Some(Motion::Line), None))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Lowercase, 1, Some(Motion::Line), None))
+        );
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_operator_action_register() {
+        let mut lexer = Lexer::new("\"acw");
+        let Some(Token::Operator(action)) = lexer.next_token() else {
+            panic!("expected an operator action");
+        };
+        assert_eq!(action.operator, Operator::Change);
+        assert_eq!(action.register, Some('a'));
+        // Change has no register in its legacy token
+        assert_eq!(action.legacy_token(), Token::TextManipulationAdvanced);
+    }
+
+    // ===========================================
+    // Bracket motions
+    // ===========================================
+
+    #[test]
+    fn test_bracket_motion_kinds() {
+        let cases = [
+            ("[[", BracketKind::Section),
+            ("][", BracketKind::Section),
+            ("]m", BracketKind::Method),
+            ("[M", BracketKind::Method),
+            ("[{", BracketKind::Block),
+            ("])", BracketKind::Block),
+            ("]q", BracketKind::Quickfix),
+            ("[l", BracketKind::Quickfix),
+            ("]d", BracketKind::Diagnostic),
+            ("[c", BracketKind::Hunk),
+            ("]b", BracketKind::Buffer),
+        ];
+        for (input, expected) in cases {
+            let mut lexer = Lexer::new(input);
+            assert_eq!(
+                lexer.next_token(),
+                Some(Token::BracketJump(expected, 1)),
+                "{input}"
+            );
+            assert!(lexer.next_token().is_none(), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_bracket_motion_with_count() {
+        let mut lexer = Lexer::new("3]qj");
+        assert_eq!(
+            lexer.next_token(),
+            Some(Token::BracketJump(BracketKind::Quickfix, 3))
+        );
+        assert_eq!(lexer.next_token(), Some(Token::MoveVerticalBasic(1)));
+    }
+
+    #[test]
+    fn test_unknown_bracket_motion() {
+        let mut lexer = Lexer::new("]zj");
+        assert_eq!(lexer.next_token(), Some(Token::Unhandled("]z".into())));
+        assert_eq!(lexer.next_token(), Some(Token::MoveVerticalBasic(1)));
+
+        let mut lexer = Lexer::new("[");
+        assert_eq!(lexer.next_token(), Some(Token::Unhandled("[".into())));
+    }
+
+    // ===========================================
+    // Paragraph and sentence motions
+    // ===========================================
+
+    #[test]
+    fn test_paragraph_and_sentence_motions() {
+        let mut lexer = Lexer::new("}{3})(2(");
+        assert_eq!(lexer.next_token(), Some(Token::MoveVerticalChunk(1)));
+        assert_eq!(lexer.next_token(), Some(Token::MoveVerticalChunk(1)));
+        assert_eq!(lexer.next_token(), Some(Token::MoveVerticalChunk(3)));
+        assert_eq!(lexer.next_token(), Some(Token::MoveHorizontalChunk(1)));
+        assert_eq!(lexer.next_token(), Some(Token::MoveHorizontalChunk(1)));
+        assert_eq!(lexer.next_token(), Some(Token::MoveHorizontalChunk(2)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_paragraph_and_sentence_operator_motions() {
+        let mut lexer = Lexer::new("d}y)2c{");
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Delete, 1, Some(Motion::Key('}')), None))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Yank, 1, Some(Motion::Key(')')), None))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Change, 2, Some(Motion::Key('{')), None))
+        );
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_paragraph_motion() {
+        let mut lexer = Lexer::new("V}}d");
+        assert_eq!(lexer.next_token(), Some(Token::VisualManipulation(2, None)));
+    }
+
+    // ===========================================
+    // Increment and decrement
+    // ===========================================
+
+    #[test]
+    fn test_increment_and_decrement() {
+        let mut lexer = Lexer::new("<C-A><C-X>5<C-A>12<C-X>");
+        assert_eq!(lexer.next_token(), Some(Token::Increment(1)));
+        assert_eq!(lexer.next_token(), Some(Token::Increment(1)));
+        assert_eq!(lexer.next_token(), Some(Token::Increment(5)));
+        assert_eq!(lexer.next_token(), Some(Token::Increment(12)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_visual_increment() {
+        let mut lexer = Lexer::new("vip<C-A>Vjg<C-A><C-V>3jg<C-X>");
+        assert_eq!(lexer.next_token(), Some(Token::Increment(1)));
+        assert_eq!(lexer.next_token(), Some(Token::IncrementSequence));
+        assert_eq!(lexer.next_token(), Some(Token::IncrementSequence));
+        assert!(lexer.next_token().is_none());
+    }
+
+    // ===========================================
+    // Key notation
+    // ===========================================
+
+    #[test]
+    fn test_literal_bar_and_lt() {
+        let mut lexer = Lexer::new("<Bar>5<Bar><lt><lt>");
+        assert_eq!(lexer.next_token(), Some(Token::JumpToLinePosition(1)));
+        assert_eq!(lexer.next_token(), Some(Token::JumpToLinePosition(5)));
+        assert_eq!(
+            lexer.next_token(),
+            Some(action(Operator::Unindent, 1, Some(Motion::Line), None))
+        );
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_literal_bar_in_search_is_not_a_key_name() {
+        // A search for the text "|enter|" ends at the real |enter| only
+        let mut lexer = Lexer::new("/<Bar>enter<Bar>|enter|j");
+        assert_eq!(lexer.next_token(), Some(Token::CommandSearch(true)));
+        assert_eq!(lexer.next_token(), Some(Token::MoveVerticalBasic(1)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_literal_lt_is_not_a_control_sequence() {
+        let mut lexer = Lexer::new("<lt>C-W>");
+        assert_eq!(lexer.next_token(), Some(Token::Unhandled("<C".into())));
+    }
+
+    #[test]
+    fn test_notation_command_terminators() {
+        assert_eq!(
+            lex_command(":%s/a/b/<CR>"),
+            Some(Token::ExCommand(
+                ExCommand::Substitute,
+                Some(ExRange::WholeFile),
+                true
+            ))
+        );
+        let mut lexer = Lexer::new("/foo<Esc>");
+        assert_eq!(lexer.next_token(), Some(Token::CommandSearch(false)));
+        // <lt>CR> is the typed text "<CR>", not the key
+        let mut lexer = Lexer::new(":e<Space>a<lt>CR>|enter|");
+        assert_eq!(
+            lexer.next_token(),
+            Some(Token::ExCommand(ExCommand::Edit, None, true))
+        );
+    }
+
+    #[test]
+    fn test_modified_keys_stay_whole() {
+        let mut lexer = Lexer::new("<M-j><S-Tab><C-S-x><F5>j");
+        for notation in ["<M-j>", "<S-Tab>", "<C-S-x>", "<F5>"] {
+            assert_eq!(lexer.next_token(), Some(Token::Unhandled(notation.into())));
+        }
+        assert_eq!(lexer.next_token(), Some(Token::MoveVerticalBasic(1)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_named_notation_keys() {
+        let mut lexer = Lexer::new("<Up><PageDown><Tab>v<Esc>");
+        assert_eq!(
+            lexer.next_token(),
+            Some(Token::AntiPattern(AntiPattern::ArrowKey))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Some(Token::AntiPattern(AntiPattern::PageKey))
+        );
+        assert_eq!(lexer.next_token(), Some(Token::JumpList));
+        assert_eq!(lexer.next_token(), Some(Token::VisualSelection(0)));
+        assert!(lexer.next_token().is_none());
+    }
+
+    #[test]
+    fn test_notation_spans_cover_whole_key() {
+        assert_eq!(
+            spans("<Bar><M-j>"),
+            vec![
+                (Token::JumpToLinePosition(1), "<Bar>"),
+                (Token::Unhandled("<M-j>".into()), "<M-j>"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_notation_split_across_batches() {
+        assert_eq!(
+            lex_batches(&["j<C-", "W>v"]),
+            vec![
+                Token::MoveVerticalBasic(1),
+                Token::WindowManagement(WindowCommand::VSplit)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escape_cancels_pending_argument() {
+        let pending = [
+            "d", "y", "c", "gu", "zf", "3d", "d3", "di", "\"ad", "f", "g", "z", "\"", "q", "@",
+            "[", "m", "'",
+        ];
+        for keys in pending {
+            for cancel in ["|escape|", "<Esc>", "<C-C>"] {
+                assert_eq!(
+                    lex_batches(&[&format!("{keys}{cancel}j")]),
+                    vec![Token::MoveVerticalBasic(1)],
+                    "{keys}{cancel}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_special_key_is_one_operator_argument() {
+        assert_eq!(
+            lex_batches(&["d<M-j>j"]),
+            vec![
+                Token::Unhandled("d<M-j>".into()),
+                Token::MoveVerticalBasic(1)
+            ]
+        );
+        assert_eq!(
+            lex_batches(&["d<Up>j"]),
+            vec![
+                Token::Unhandled("d|up|".into()),
+                Token::MoveVerticalBasic(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_special_keys_type_their_char_as_argument() {
+        for input in ["r<CR>", "r|enter|", "r<Tab>"] {
+            assert_eq!(
+                lex_batches(&[input]),
+                vec![Token::TextManipulationBasic(1)],
+                "{input}"
+            );
+        }
+        assert_eq!(lex_batches(&["f<Tab>"]), vec![Token::JumpToHorizontal]);
+        assert_eq!(
+            lex_batches(&["df|tab|"]),
+            vec![action(
+                Operator::Delete,
+                1,
+                Some(Motion::Find('f', '\t')),
+                None
+            )]
+        );
+    }
+
+    #[test]
+    fn test_operator_search_motion() {
+        assert_eq!(
+            lex_batches(&["d/foo|enter|j"]),
+            vec![
+                action(Operator::Delete, 1, Some(Motion::Search('/')), None),
+                Token::MoveVerticalBasic(1)
+            ]
+        );
+        assert_eq!(
+            lex_batches(&["2y?a<Space>b<CR>"]),
+            vec![action(Operator::Yank, 2, Some(Motion::Search('?')), None)]
+        );
+        // The operator never runs when the pattern is not entered
+        assert_eq!(
+            lex_batches(&["d/foo|escape|j"]),
+            vec![Token::MoveVerticalBasic(1)]
+        );
+    }
+
+    #[test]
+    fn test_argument_notation_split_across_batches() {
+        assert_eq!(
+            lex_batches(&["jd<C-", "C>k"]),
+            vec![Token::MoveVerticalBasic(1), Token::MoveVerticalBasic(1)]
+        );
+        assert_eq!(
+            lex_batches(&["f|esc", "ape|j"]),
+            vec![Token::MoveVerticalBasic(1)]
+        );
+    }
+
+    #[test]
+    fn test_escaped_visual_argument_keeps_selection() {
+        assert_eq!(
+            lex_batches(&["vf|escape|ly"]),
+            vec![Token::VisualYank(1, None)]
+        );
+    }
+    // =========================================================================
+    // Mode-Tagged Input Tests
+    // =========================================================================
+
+    /// Lex `(keys, modes)` batches the way `process_mode_batch` does, then flush
+    fn lex_mode_batches(batches: &[(&str, &str)]) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut checkpoint = Checkpoint::default();
+        for (i, (batch, modes)) in batches.iter().enumerate() {
+            let input = format!("{}{batch}", checkpoint.pending());
+            let mut input_modes = checkpoint.pending_modes().clone();
+            input_modes.append(checkpoint.pending().len(), &ModeRuns::decode(modes));
+            let mut lexer =
+                Lexer::resume(&input, checkpoint, i + 1 < batches.len()).with_modes(input_modes);
+            while let Some(token) = lexer.next_token() {
+                tokens.push(token);
+            }
+            checkpoint = lexer.checkpoint();
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_confirm_prompt_answers_are_skipped() {
+        assert_eq!(
+            lex_mode_batches(&[(":%s/a/b/gc|enter|yynq", "n:1;c:16;r?:4;")]),
+            vec![Token::ExCommand(
+                ExCommand::Substitute,
+                Some(ExRange::WholeFile),
+                true
+            )]
+        );
+    }
+
+    #[test]
+    fn test_hit_enter_prompt_keys_are_commands() {
+        assert_eq!(
+            lex_mode_batches(&[(":ls|enter|j", "n:1;c:9;r:1;")]),
+            vec![
+                Token::ExCommand(ExCommand::Buffer, None, true),
+                Token::MoveVerticalBasic(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_insert_mode_keys_are_skipped() {
+        assert_eq!(
+            lex_mode_batches(&[("ihello|escape|j", "n:1;i:13;n:1;")]),
+            vec![Token::InsertBasic(1), Token::MoveVerticalBasic(1)]
+        );
+    }
+
+    #[test]
+    fn test_visual_line_yank_with_modes() {
+        assert_eq!(
+            lex_mode_batches(&[("vjYjj", "n:1;v:2;n:2;")]),
+            vec![
+                Token::VisualYank(1, None),
+                Token::MoveVerticalBasic(1),
+                Token::MoveVerticalBasic(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operator_search_left_without_terminator() {
+        assert_eq!(
+            lex_mode_batches(&[("d/fooj", "n:1;no:1;c:3;n:1;")]),
+            vec![Token::MoveVerticalBasic(1)]
+        );
+    }
+
+    #[test]
+    fn test_insert_runs_without_bytes_are_skipped() {
+        assert_eq!(
+            lex_mode_batches(&[("ij", "n:1:0;i:0:10;i:0:20;n:1:30;")]),
+            vec![Token::InsertBasic(1), Token::MoveVerticalBasic(1)]
+        );
+    }
+
+    #[test]
+    fn test_command_line_left_without_terminator() {
+        // <C-C> closes the command line, the `j` after it is a motion
+        assert_eq!(
+            lex_mode_batches(&[(":w<C-C>j", "n:1;c:6;n:1;")]),
+            vec![Token::SaveFile(false), Token::MoveVerticalBasic(1)]
+        );
+        assert_eq!(
+            lex_mode_batches(&[("/foo<C-C>n", "n:1;c:8;n:1;")]),
+            vec![Token::CommandSearch(false), Token::SearchRepeat]
+        );
+    }
+
+    #[test]
+    fn test_visual_command_line_left_without_terminator() {
+        assert_eq!(
+            lex_mode_batches(&[("vj:<C-C>j", "n:1;v:2;c:5;n:1;")]),
+            vec![
+                Token::VisualSelection(1),
+                Token::Command(false),
+                Token::MoveVerticalBasic(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_line_opened_by_mapping() {
+        // `;` mapped to `:`, the command line keys still make a command
+        assert_eq!(
+            lex_mode_batches(&[(";w|enter|", "n:1;c:8;")]),
+            vec![Token::SearchRepeat, Token::SaveFile(true)]
+        );
+    }
+
+    #[test]
+    fn test_visual_mode_left_without_escape() {
+        assert_eq!(
+            lex_mode_batches(&[("vjj<C-C>w", "n:1;v:7;n:1;")]),
+            vec![Token::VisualSelection(2), Token::MoveHorizontalChunk(1)]
+        );
+    }
+
+    #[test]
+    fn test_visual_mode_entered_without_v() {
+        assert_eq!(
+            lex_mode_batches(&[("gvjd", "n:2;v:2;")]),
+            vec![
+                Token::Unhandled("gv".into()),
+                Token::VisualManipulation(1, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replace_mode_left_without_escape() {
+        assert_eq!(
+            lex_mode_batches(&[("Rab<C-C>j", "n:1;R:7;n:1;")]),
+            vec![Token::TextManipulationAdvanced, Token::MoveVerticalBasic(1)]
+        );
+    }
+
+    #[test]
+    fn test_orphaned_operator_pending_keys_are_skipped() {
+        assert_eq!(
+            lex_mode_batches(&[("jw", "n:1;no:1;")]),
+            vec![Token::MoveVerticalBasic(1)]
+        );
+    }
+
+    #[test]
+    fn test_untagged_keys_are_inferred() {
+        assert_eq!(
+            lex_mode_batches(&[(":w|enter|yy", "")]),
+            vec![
+                Token::SaveFile(true),
+                action(Operator::Yank, 1, Some(Motion::Line), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_modes_carry_across_batches() {
+        assert_eq!(
+            lex_mode_batches(&[(":%s/a", "n:1;c:4;"), ("/b/c|enter|yq", "c:11;r?:2;")]),
+            vec![Token::ExCommand(
+                ExCommand::Substitute,
+                Some(ExRange::WholeFile),
+                true
+            )]
+        );
+    }
 }
@@ -2,13 +2,22 @@
 #![allow(clippy::cast_sign_loss)]
 #![allow(clippy::cast_precision_loss)]
 
-use api::{enable_token_log, get_skill_details, get_user_data, process_batch, setup_tables};
+use api::{
+    enable_hardtime, enable_token_log, flush_batch, flush_mode_batch, get_skill_details,
+    get_user_data, process_batch, process_mode_batch, set_leaders, set_recording, setup_tables,
+};
 use nvim_oxi::{Dictionary, Function, Object};
 
+mod activity;
 mod api;
+mod carryover;
 mod db;
+mod echo;
+mod hardtime;
+mod leader;
 mod levels;
 mod lexer;
+mod mode;
 mod parse_utils;
 mod skill_data;
 mod skills;
@@ -18,15 +27,27 @@ mod token_log;
 #[nvim_oxi::plugin]
 fn vimscape_backend() -> nvim_oxi::Dictionary {
     let process_batch_fn = Function::from_fn(process_batch);
+    let flush_batch_fn = Function::from_fn(flush_batch);
+    let process_mode_batch_fn = Function::from_fn(process_mode_batch);
+    let flush_mode_batch_fn = Function::from_fn(flush_mode_batch);
     let get_user_data_fn = Function::from_fn(get_user_data);
     let setup_tables_fn = Function::from_fn(setup_tables);
     let get_skill_details_fn = Function::from_fn(get_skill_details);
     let enable_token_log_fn = Function::from_fn(enable_token_log);
+    let enable_hardtime_fn = Function::from_fn(enable_hardtime);
+    let set_leaders_fn = Function::from_fn(set_leaders);
+    let set_recording_fn = Function::from_fn(set_recording);
     Dictionary::from_iter([
         ("process_batch", Object::from(process_batch_fn)),
+        ("flush_batch", Object::from(flush_batch_fn)),
+        ("process_mode_batch", Object::from(process_mode_batch_fn)),
+        ("flush_mode_batch", Object::from(flush_mode_batch_fn)),
         ("get_user_data", Object::from(get_user_data_fn)),
         ("setup_tables", Object::from(setup_tables_fn)),
         ("get_skill_details", Object::from(get_skill_details_fn)),
         ("enable_token_log", Object::from(enable_token_log_fn)),
+        ("enable_hardtime", Object::from(enable_hardtime_fn)),
+        ("set_leaders", Object::from(set_leaders_fn)),
+        ("set_recording", Object::from(set_recording_fn)),
     ])
 }
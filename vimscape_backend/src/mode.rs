@@ -0,0 +1,340 @@
+//! Neovim modes of the typed keys
+//!
+//! The Lua side reports the `nvim_get_mode()` mode each key was typed in as
+//! runs of `{mode}:{bytes};`, `bytes` being how much of the batch string the
+//! run covers. `n:3;no:1;c:5;` tags `dw:w|enter|`-like input as three normal
+//! mode bytes, one operator-pending byte and five command-line bytes.
+//!
+//! A run can also carry the monotonic time in milliseconds its first key was
+//! typed at, `{mode}:{bytes}:{ms};`, which `activity` measures the batch with.
+//! Keys typed in insert mode arrive as runs of no bytes (`i:0:1200;`), which
+//! only carry their time and tag nothing.
+
+use std::ops::Range;
+
+/// The modes the lexer tells apart, see `:help mode()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    // n, niI, nt, ...
+    Normal,
+
+    // no, nov, noV, no<C-V>
+    OperatorPending,
+
+    // v, vs
+    Visual,
+
+    // V, Vs
+    VisualLine,
+
+    // <C-V>, <C-V>s
+    VisualBlock,
+
+    // s, S, <C-S>
+    Select,
+
+    // i, ic, ix
+    Insert,
+
+    // R, Rc, Rv, ...
+    Replace,
+
+    // c, cv, cr, ...
+    Cmdline,
+
+    // r, the hit-enter prompt runs any key but |enter|, |space| and |escape| as a command
+    HitEnter,
+
+    // rm (-- more --), r? (:s///c and other confirm prompts)
+    Prompt,
+
+    // t, ! (shell or external command running)
+    Terminal,
+}
+
+impl Mode {
+    /// Parse a mode string from `nvim_get_mode()`. None for anything unknown.
+    pub fn from_nvim(mode: &str) -> Option<Mode> {
+        if mode.starts_with("no") {
+            return Some(Mode::OperatorPending);
+        }
+        match mode.chars().next()? {
+            'n' => Some(Mode::Normal),
+            'v' => Some(Mode::Visual),
+            'V' => Some(Mode::VisualLine),
+            '\x16' => Some(Mode::VisualBlock),
+            's' | 'S' | '\x13' => Some(Mode::Select),
+            'i' => Some(Mode::Insert),
+            'R' => Some(Mode::Replace),
+            'c' => Some(Mode::Cmdline),
+            'r' if mode.len() > 1 => Some(Mode::Prompt),
+            'r' => Some(Mode::HitEnter),
+            't' | '!' => Some(Mode::Terminal),
+            _ => None,
+        }
+    }
+
+    pub fn is_visual(self) -> bool {
+        matches!(self, Mode::Visual | Mode::VisualLine | Mode::VisualBlock)
+    }
+
+    /// Keys typed in this mode are not commands: insert mode text, answers to
+    /// prompts, terminal input and select mode typing
+    pub fn is_untracked(self) -> bool {
+        matches!(
+            self,
+            Mode::Insert | Mode::Select | Mode::Prompt | Mode::Terminal
+        )
+    }
+}
+
+/// One `{mode}:{bytes}[:{ms}];` run of the framed batch
+pub struct Frame<'a> {
+    pub mode: &'a str,
+    pub len: usize,
+    pub time: Option<i64>,
+}
+
+/// Split the framed runs sent by the Lua side, stopping at the first
+/// malformed one
+pub fn frames(encoded: &str) -> impl Iterator<Item = Frame<'_>> {
+    encoded.split_terminator(';').map_while(|frame| {
+        let mut fields = frame.split(':');
+        let mode = fields.next()?;
+        let len = fields.next()?.parse().ok()?;
+        let time = match fields.next() {
+            Some(time) => Some(time.parse().ok()?),
+            None => None,
+        };
+        Some(Frame { mode, len, time })
+    })
+}
+
+/// The mode of every byte of the lexer input, as `(start offset, mode)` runs.
+/// `None` marks keys whose mode was not reported, which are lexed by
+/// inference as if no modes were known.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModeRuns {
+    runs: Vec<(usize, Option<Mode>)>,
+}
+
+impl ModeRuns {
+    /// Decode the `{mode}:{bytes};` runs sent by the Lua side. Decoding stops
+    /// at the first malformed run, leaving the rest of the input untagged.
+    pub fn decode(encoded: &str) -> ModeRuns {
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for frame in frames(encoded) {
+            runs.push((start, Mode::from_nvim(frame.mode)));
+            start += frame.len;
+        }
+        if !runs.is_empty() {
+            runs.push((start, None));
+        }
+        let mut decoded = ModeRuns::default();
+        for (start, mode) in runs {
+            decoded.push(start, mode);
+        }
+        decoded
+    }
+
+    /// The mode of the key starting at byte `offset`
+    pub fn at(&self, offset: usize) -> Option<Mode> {
+        let index = self.runs.partition_point(|&(start, _)| start <= offset);
+        index.checked_sub(1).and_then(|index| self.runs[index].1)
+    }
+
+    /// Where the run holding the key at byte `offset` ends, None if it runs
+    /// to the end of the input
+    pub fn run_end(&self, offset: usize) -> Option<usize> {
+        self.runs
+            .iter()
+            .map(|&(start, _)| start)
+            .find(|&start| start > offset)
+    }
+
+    /// Runs for the input that follows the first `offset` bytes
+    pub fn split_off(&self, offset: usize) -> ModeRuns {
+        let mut rest = ModeRuns::default();
+        if self.runs.is_empty() {
+            return rest;
+        }
+        rest.push(0, self.at(offset));
+        for &(start, mode) in self.runs.iter().filter(|(start, _)| *start > offset) {
+            rest.push(start - offset, mode);
+        }
+        rest
+    }
+
+    /// Append the runs of `next`, which tags the input following the first
+    /// `len` bytes
+    pub fn append(&mut self, len: usize, next: &ModeRuns) {
+        if next.runs.is_empty() {
+            self.push(len, None);
+        }
+        for &(start, mode) in &next.runs {
+            self.push(start + len, mode);
+        }
+    }
+
+    /// Drop the modes of the byte ranges cut from the input, shifting the
+    /// runs after them back. The ranges must be sorted and not overlap.
+    pub fn remove_ranges(&mut self, removed: &[Range<usize>]) {
+        let runs = std::mem::take(&mut self.runs);
+        for (start, mode) in runs {
+            let cut: usize = removed
+                .iter()
+                .filter(|range| range.start < start)
+                .map(|range| range.end.min(start) - range.start)
+                .sum();
+            self.push(start - cut, mode);
+        }
+    }
+
+    // A run starting where the last one starts replaces it, and a run with
+    // the same mode as the last one extends it
+    fn push(&mut self, start: usize, mode: Option<Mode>) {
+        if let Some(last) = self.runs.last_mut()
+            && last.0 == start
+        {
+            last.1 = mode;
+            let len = self.runs.len();
+            if len > 1 && self.runs[len - 2].1 == mode {
+                self.runs.pop();
+            }
+            return;
+        }
+        if self.runs.last().is_some_and(|&(_, last)| last == mode) {
+            return;
+        }
+        self.runs.push((start, mode));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_nvim() {
+        assert_eq!(Mode::from_nvim("n"), Some(Mode::Normal));
+        assert_eq!(Mode::from_nvim("niI"), Some(Mode::Normal));
+        assert_eq!(Mode::from_nvim("no"), Some(Mode::OperatorPending));
+        assert_eq!(Mode::from_nvim("nov"), Some(Mode::OperatorPending));
+        assert_eq!(Mode::from_nvim("v"), Some(Mode::Visual));
+        assert_eq!(Mode::from_nvim("V"), Some(Mode::VisualLine));
+        assert_eq!(Mode::from_nvim("\x16"), Some(Mode::VisualBlock));
+        assert_eq!(Mode::from_nvim("s"), Some(Mode::Select));
+        assert_eq!(Mode::from_nvim("ic"), Some(Mode::Insert));
+        assert_eq!(Mode::from_nvim("Rv"), Some(Mode::Replace));
+        assert_eq!(Mode::from_nvim("c"), Some(Mode::Cmdline));
+        assert_eq!(Mode::from_nvim("r?"), Some(Mode::Prompt));
+        assert_eq!(Mode::from_nvim("rm"), Some(Mode::Prompt));
+        assert_eq!(Mode::from_nvim("r"), Some(Mode::HitEnter));
+        assert_eq!(Mode::from_nvim("t"), Some(Mode::Terminal));
+        assert_eq!(Mode::from_nvim("!"), Some(Mode::Terminal));
+        assert_eq!(Mode::from_nvim(""), None);
+        assert_eq!(Mode::from_nvim("x"), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        let modes = ModeRuns::decode("n:1;no:1;c:8;");
+        assert_eq!(modes.at(0), Some(Mode::Normal));
+        assert_eq!(modes.at(1), Some(Mode::OperatorPending));
+        assert_eq!(modes.at(2), Some(Mode::Cmdline));
+        assert_eq!(modes.at(9), Some(Mode::Cmdline));
+        // Past the last run nothing is known
+        assert_eq!(modes.at(10), None);
+    }
+
+    #[test]
+    fn test_run_end() {
+        let modes = ModeRuns::decode("n:1;no:1;c:8;");
+        assert_eq!(modes.run_end(0), Some(1));
+        assert_eq!(modes.run_end(4), Some(10));
+        assert_eq!(modes.run_end(10), None);
+        assert_eq!(ModeRuns::default().run_end(0), None);
+    }
+
+    #[test]
+    fn test_decode_merges_runs_of_the_same_mode() {
+        assert_eq!(ModeRuns::decode("n:2;n:3;"), ModeRuns::decode("n:5;"));
+    }
+
+    #[test]
+    fn test_decode_skips_runs_without_bytes() {
+        assert_eq!(
+            ModeRuns::decode("n:1:0;i:0:10;i:0:20;n:1:30;"),
+            ModeRuns::decode("n:2;")
+        );
+    }
+
+    #[test]
+    fn test_decode_unknown_and_malformed() {
+        assert_eq!(ModeRuns::decode(""), ModeRuns::default());
+        assert_eq!(ModeRuns::decode(":3;n:1;").at(0), None);
+        assert_eq!(ModeRuns::decode(":3;n:1;").at(3), Some(Mode::Normal));
+
+        let modes = ModeRuns::decode("n:2;garbage;c:4;");
+        assert_eq!(modes.at(1), Some(Mode::Normal));
+        assert_eq!(modes.at(2), None);
+    }
+
+    #[test]
+    fn test_decode_timed_runs() {
+        assert_eq!(
+            ModeRuns::decode("n:1:100;n:1:250;c:3:300;"),
+            ModeRuns::decode("n:2;c:3;")
+        );
+        // A run with a bad timestamp is malformed
+        assert_eq!(ModeRuns::decode("n:1:soon;c:3;"), ModeRuns::default());
+    }
+
+    #[test]
+    fn test_split_off_and_append() {
+        let modes = ModeRuns::decode("n:2;c:3;n:1;");
+        let rest = modes.split_off(3);
+        assert_eq!(rest.at(0), Some(Mode::Cmdline));
+        assert_eq!(rest.at(2), Some(Mode::Normal));
+        assert_eq!(rest.at(3), None);
+
+        let mut joined = rest;
+        joined.append(3, &ModeRuns::decode("v:2;"));
+        assert_eq!(joined.at(3), Some(Mode::Visual));
+        assert_eq!(joined.at(5), None);
+    }
+
+    #[test]
+    fn test_append_untagged_input() {
+        let mut modes = ModeRuns::decode("c:2;");
+        modes.append(2, &ModeRuns::default());
+        assert_eq!(modes.at(1), Some(Mode::Cmdline));
+        assert_eq!(modes.at(2), None);
+
+        // Untagged pending keys followed by a tagged batch
+        let mut modes = ModeRuns::default();
+        modes.append(2, &ModeRuns::decode("n:1;"));
+        assert_eq!(modes.at(0), None);
+        assert_eq!(modes.at(2), Some(Mode::Normal));
+    }
+
+    #[test]
+    fn test_remove_ranges() {
+        // "ab" normal, "cdef" cmdline, "gh" normal, with "bc" and "ef" cut
+        let mut modes = ModeRuns::decode("n:2;c:4;n:2;");
+        modes.remove_ranges(&[1..3, 4..6]);
+        assert_eq!(modes.at(0), Some(Mode::Normal));
+        assert_eq!(modes.at(1), Some(Mode::Cmdline));
+        assert_eq!(modes.at(2), Some(Mode::Normal));
+        assert_eq!(modes.at(3), Some(Mode::Normal));
+        assert_eq!(modes.at(4), None);
+    }
+
+    #[test]
+    fn test_remove_whole_run() {
+        let mut modes = ModeRuns::decode("n:2;c:2;n:2;");
+        modes.remove_ranges(&[2..3, 3..4]);
+        assert_eq!(modes, ModeRuns::decode("n:4;"));
+    }
+}
@@ -1,27 +1,385 @@
-use crate::{skills::Skills, token::Token};
+use crate::{
+    skills::Skills,
+    token::{BracketKind, ExCommand, ExRange, Token, WindowCommand},
+};
 
-pub fn parse_action_into_skill(token: &Token) -> Option<Skills> {
+// Extra Clipboard XP for yanking, deleting or pasting through a named
+// ("a-"z, "A-"Z) or system ("+, "*) register
+const REGISTER_BONUS: i32 = 5;
+
+fn register_bonus(register: Option<char>) -> i32 {
+    match register {
+        Some(reg) if reg.is_ascii_alphabetic() || reg == '+' || reg == '*' => REGISTER_BONUS,
+        _ => 0,
+    }
+}
+
+// Extra XP for pointing an Ex command at lines other than the cursor line
+fn range_bonus(range: Option<&ExRange>) -> i32 {
+    match range {
+        None => 0,
+        Some(ExRange::WholeFile | ExRange::Single(_)) => 5,
+        Some(ExRange::Span(_, _)) => 10,
+    }
+}
+
+fn ex_command_skills(command: ExCommand, range: Option<&ExRange>) -> Vec<Skills> {
+    let bonus = range_bonus(range);
+    match command {
+        // Pattern driven edits over a range are the top end of Ex usage
+        ExCommand::Substitute => vec![Skills::TextManipulation(20 + bonus), Skills::Search(10)],
+        ExCommand::Global => vec![Skills::Finesse(20 + bonus), Skills::Search(10)],
+        ExCommand::Normal => vec![Skills::Macros(15 + bonus)],
+        ExCommand::Sort | ExCommand::Move | ExCommand::Copy => {
+            vec![Skills::TextManipulation(15 + bonus)]
+        }
+        ExCommand::Delete | ExCommand::Join => vec![Skills::TextManipulation(10 + bonus)],
+        ExCommand::Yank => vec![Skills::Clipboard(10 + bonus)],
+        ExCommand::Edit => vec![Skills::CodeFlow(10)],
+        ExCommand::Quickfix => vec![Skills::Debugging(10)],
+        ExCommand::Buffer | ExCommand::Tab | ExCommand::Args => {
+            vec![Skills::BufferManagement(10)]
+        }
+        ExCommand::Quit | ExCommand::Make => vec![Skills::Finesse(10)],
+        ExCommand::WriteQuit => vec![Skills::Saving(10 + bonus)],
+        ExCommand::Split => vec![Skills::WindowManagement(10)],
+    }
+}
+
+// Reshaping the layout is worth more than hopping between windows
+fn window_exp(command: WindowCommand) -> i32 {
+    match command {
+        WindowCommand::Focus
+        | WindowCommand::Split
+        | WindowCommand::VSplit
+        | WindowCommand::Close
+        | WindowCommand::Only => 10,
+        WindowCommand::Equalize => 15,
+        // The count of a resize is a size in lines or columns, so it stops
+        // adding XP at 10
+        WindowCommand::Resize(modifier) => 10 + modifier.min(10) * 5,
+        WindowCommand::Rotate | WindowCommand::Move => 20,
+    }
+}
+
+fn bracket_skill(kind: BracketKind, count: i32) -> Skills {
+    let exp = count * 10;
+    match kind {
+        BracketKind::Section | BracketKind::Method | BracketKind::Block => Skills::CodeFlow(exp),
+        BracketKind::Quickfix | BracketKind::Diagnostic => Skills::Debugging(exp),
+        BracketKind::Hunk => Skills::Diffing(exp),
+        BracketKind::Buffer => Skills::BufferManagement(exp),
+    }
+}
+
+pub fn parse_action_into_skill(token: &Token) -> Vec<Skills> {
     match token {
-        Token::MoveVerticalBasic(modifier) => Some(Skills::VerticalNavigation(*modifier)),
-        Token::MoveHorizontalBasic(modifier) => Some(Skills::HorizontalNavigation(*modifier)),
-        Token::MoveVerticalChunk(modifier) => Some(Skills::VerticalNavigation(modifier * 5)),
-        Token::MoveHorizontalChunk(modifier) => Some(Skills::HorizontalNavigation(modifier * 5)),
-        Token::JumpToHorizontal => Some(Skills::HorizontalNavigation(10)),
-        Token::JumpToLineNumber(_) | Token::JumpToVertical => Some(Skills::VerticalNavigation(10)),
-        Token::JumpFromContext | Token::Marks => Some(Skills::CodeFlow(10)),
-        Token::CameraMovement => Some(Skills::CameraMovement(10)),
-        Token::WindowManagement => Some(Skills::WindowManagement(10)),
-        Token::TextManipulationBasic(modifier) | Token::DeleteText(modifier) => {
-            Some(Skills::TextManipulation(*modifier))
-        }
-        Token::TextManipulationAdvanced => Some(Skills::TextManipulation(10)),
-        Token::YankPaste | Token::UndoRedo => Some(Skills::Clipboard(10)),
-        Token::DotRepeat => Some(Skills::Finesse(10)),
-        Token::CommandSearch(completed) => Some(Skills::Search(if *completed { 10 } else { 1 })),
-        Token::Command(completed) => Some(Skills::Finesse(if *completed { 10 } else { 1 })),
-        Token::HelpPage(completed) => Some(Skills::Knowledge(if *completed { 10 } else { 1 })),
-        Token::SaveFile(completed) => Some(Skills::Saving(if *completed { 10 } else { 1 })),
-        Token::SearchRepeat => Some(Skills::Search(5)),
-        Token::Unhandled(_) => None,
+        Token::MoveVerticalBasic(modifier) => vec![Skills::VerticalNavigation(*modifier)],
+        Token::MoveHorizontalBasic(modifier) => vec![Skills::HorizontalNavigation(*modifier)],
+        Token::MoveVerticalChunk(modifier) => vec![Skills::VerticalNavigation(modifier * 5)],
+        Token::MoveHorizontalChunk(modifier) => vec![Skills::HorizontalNavigation(modifier * 5)],
+        Token::JumpToHorizontal => vec![Skills::HorizontalNavigation(10)],
+        // The count of | is a column number, so it stops adding XP at 10
+        Token::JumpToLinePosition(modifier) => {
+            vec![Skills::HorizontalNavigation(5 + (*modifier).min(10) * 5)]
+        }
+        Token::JumpToLineNumber(_) | Token::JumpToVertical => vec![Skills::VerticalNavigation(10)],
+        Token::JumpFromContext | Token::Marks | Token::JumpList | Token::GotoDefinition => {
+            vec![Skills::CodeFlow(10)]
+        }
+        Token::CameraMovement | Token::FoldCreate | Token::FoldToggle => {
+            vec![Skills::CameraMovement(10)]
+        }
+        Token::FoldDelete => vec![Skills::CameraMovement(5)],
+        Token::FoldMove(modifier) => vec![Skills::CameraMovement(modifier * 5)],
+        Token::BufferSwitch => vec![Skills::BufferManagement(10)],
+        Token::BracketJump(kind, modifier) => vec![bracket_skill(*kind, *modifier)],
+        Token::WindowManagement(command) => vec![Skills::WindowManagement(window_exp(*command))],
+        Token::TextManipulationBasic(modifier)
+        | Token::IndentText(modifier)
+        | Token::InsertBasic(modifier) => {
+            vec![Skills::TextManipulation(*modifier)]
+        }
+        Token::DeleteText(modifier, register) => {
+            let bonus = register_bonus(*register);
+            if bonus > 0 {
+                vec![
+                    Skills::TextManipulation(*modifier),
+                    Skills::Clipboard(bonus),
+                ]
+            } else {
+                vec![Skills::TextManipulation(*modifier)]
+            }
+        }
+        // A, I, o and O replace a motion plus i or a, e.g. A over $a. A count
+        // only repeats the typed text, so it earns a flat bonus.
+        Token::InsertAdvanced(modifier) => {
+            vec![Skills::TextManipulation(if *modifier > 1 { 10 } else { 5 })]
+        }
+        Token::TextManipulationAdvanced | Token::FormatText | Token::FilterText => {
+            vec![Skills::TextManipulation(10)]
+        }
+        // Editing a number in place beats retyping it, more so with a count
+        Token::Increment(modifier) => vec![
+            Skills::TextManipulation(10),
+            Skills::Finesse(if *modifier > 1 { 10 } else { 5 }),
+        ],
+        Token::IncrementSequence => vec![Skills::TextManipulation(10), Skills::Finesse(15)],
+        Token::YankPaste(register) => vec![Skills::Clipboard(10 + register_bonus(*register))],
+        Token::UndoRedo => vec![Skills::Clipboard(10)],
+        Token::DotRepeat => vec![Skills::Finesse(10)],
+        Token::CommandSearch(completed) => vec![Skills::Search(if *completed { 10 } else { 1 })],
+        Token::Command(completed) => vec![Skills::Finesse(if *completed { 10 } else { 1 })],
+        Token::HelpPage(completed) => vec![Skills::Knowledge(if *completed { 10 } else { 1 })],
+        Token::SaveFile(completed) => vec![Skills::Saving(if *completed { 10 } else { 1 })],
+        Token::ExCommand(command, range, completed) => {
+            if *completed {
+                ex_command_skills(*command, range.as_ref())
+            } else {
+                vec![Skills::Finesse(1)]
+            }
+        }
+        Token::SearchRepeat => vec![Skills::Search(5)],
+        // A count picks the nth match, it doesn't search any harder
+        Token::SearchWord(_) => vec![Skills::Search(10)],
+        Token::MacroRecordStart => vec![Skills::Macros(10)],
+        Token::MacroRecordStop => vec![Skills::Macros(5)],
+        // A count replays the macro more times, it takes no more skill to type
+        Token::MacroPlayback(modifier) => {
+            vec![Skills::Macros(if *modifier > 1 { 15 } else { 10 })]
+        }
+        Token::VisualManipulation(motions, register) => {
+            let bonus = register_bonus(*register);
+            if bonus > 0 {
+                vec![
+                    Skills::TextManipulation(10 + motions),
+                    Skills::Clipboard(bonus),
+                ]
+            } else {
+                vec![Skills::TextManipulation(10 + motions)]
+            }
+        }
+        Token::VisualYank(motions, register) => {
+            vec![Skills::Clipboard(10 + motions + register_bonus(*register))]
+        }
+        Token::VisualSelection(_) => vec![Skills::TextManipulation(1)],
+        Token::Operator(action) => parse_action_into_skill(&action.legacy_token()),
+        // Anti-patterns never pay, hardtime mode records them as penalties
+        Token::AntiPattern(_) | Token::Unhandled(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{Motion, Operator, OperatorAction};
+
+    fn skill_totals(token: &Token) -> Vec<(String, i32)> {
+        parse_action_into_skill(token)
+            .iter()
+            .map(|skill| (skill.to_str(), skill.get_exp_from_skill()))
+            .collect()
+    }
+
+    #[test]
+    fn test_yank_without_register() {
+        assert_eq!(
+            skill_totals(&Token::YankPaste(None)),
+            vec![("Clipboard".to_string(), 10)]
+        );
+    }
+
+    #[test]
+    fn test_yank_named_and_system_registers_pay_bonus() {
+        for reg in ['a', 'Z', '+', '*'] {
+            assert_eq!(
+                skill_totals(&Token::YankPaste(Some(reg))),
+                vec![("Clipboard".to_string(), 15)],
+                "register {reg} should pay the bonus"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unnamed_and_black_hole_registers_pay_no_bonus() {
+        for reg in ['"', '_', '0', '-'] {
+            assert_eq!(
+                skill_totals(&Token::YankPaste(Some(reg))),
+                vec![("Clipboard".to_string(), 10)],
+                "register {reg} should not pay the bonus"
+            );
+        }
+    }
+
+    #[test]
+    fn test_delete_into_named_register_pays_clipboard_bonus() {
+        assert_eq!(
+            skill_totals(&Token::DeleteText(3, Some('a'))),
+            vec![
+                ("TextManipulation".to_string(), 3),
+                ("Clipboard".to_string(), 5)
+            ]
+        );
+        assert_eq!(
+            skill_totals(&Token::DeleteText(3, Some('_'))),
+            vec![("TextManipulation".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_visual_register_pays_clipboard_bonus() {
+        assert_eq!(
+            skill_totals(&Token::VisualYank(1, Some('a'))),
+            vec![("Clipboard".to_string(), 16)]
+        );
+        assert_eq!(
+            skill_totals(&Token::VisualManipulation(0, Some('+'))),
+            vec![
+                ("TextManipulation".to_string(), 10),
+                ("Clipboard".to_string(), 5)
+            ]
+        );
+        assert_eq!(
+            skill_totals(&Token::VisualManipulation(0, None)),
+            vec![("TextManipulation".to_string(), 10)]
+        );
+    }
+
+    #[test]
+    fn test_substitute_pays_more_than_generic_command() {
+        assert_eq!(
+            skill_totals(&Token::ExCommand(ExCommand::Substitute, None, true)),
+            vec![
+                ("TextManipulation".to_string(), 20),
+                ("Search".to_string(), 10)
+            ]
+        );
+        assert_eq!(
+            skill_totals(&Token::Command(true)),
+            vec![("Finesse".to_string(), 10)]
+        );
+    }
+
+    #[test]
+    fn test_quit_pays_like_generic_command() {
+        assert_eq!(
+            skill_totals(&Token::ExCommand(ExCommand::Quit, None, true)),
+            skill_totals(&Token::Command(true))
+        );
+    }
+
+    #[test]
+    fn test_cancelled_ex_command_pays_minimum() {
+        assert_eq!(
+            skill_totals(&Token::ExCommand(ExCommand::Global, None, false)),
+            vec![("Finesse".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_ranged_ex_command_pays_more() {
+        let plain = skill_totals(&Token::ExCommand(ExCommand::Delete, None, true));
+        let whole = skill_totals(&Token::ExCommand(
+            ExCommand::Delete,
+            Some(ExRange::WholeFile),
+            true,
+        ));
+        assert_eq!(plain, vec![("TextManipulation".to_string(), 10)]);
+        assert_eq!(whole, vec![("TextManipulation".to_string(), 15)]);
+    }
+
+    #[test]
+    fn test_operator_action_pays_like_legacy_token() {
+        let action = OperatorAction {
+            operator: Operator::Delete,
+            motion: Some(Motion::Key('w')),
+            text_object: None,
+            count: 3,
+            register: Some('a'),
+        };
+        assert_eq!(
+            skill_totals(&Token::Operator(action)),
+            skill_totals(&Token::DeleteText(3, Some('a')))
+        );
+    }
+
+    #[test]
+    fn test_window_layout_pays_more_than_focus() {
+        let exp = |command| skill_totals(&Token::WindowManagement(command))[0].1;
+        assert_eq!(exp(WindowCommand::Focus), 10);
+        assert_eq!(exp(WindowCommand::Resize(1)), 15);
+        assert_eq!(exp(WindowCommand::Resize(4)), 30);
+        assert_eq!(exp(WindowCommand::Resize(999)), 60);
+        assert_eq!(exp(WindowCommand::Move), 20);
+    }
+
+    #[test]
+    fn test_bracket_jumps_route_by_kind() {
+        assert_eq!(
+            skill_totals(&Token::BracketJump(BracketKind::Method, 1)),
+            vec![("CodeFlow".to_string(), 10)]
+        );
+        assert_eq!(
+            skill_totals(&Token::BracketJump(BracketKind::Diagnostic, 3)),
+            vec![("Debugging".to_string(), 30)]
+        );
+        assert_eq!(
+            skill_totals(&Token::BracketJump(BracketKind::Hunk, 1)),
+            vec![("Diffing".to_string(), 10)]
+        );
+        assert_eq!(
+            skill_totals(&Token::ExCommand(ExCommand::Quickfix, None, true)),
+            vec![("Debugging".to_string(), 10)]
+        );
+    }
+
+    #[test]
+    fn test_line_position_count_scales_xp() {
+        let exp = |count| skill_totals(&Token::JumpToLinePosition(count))[0].1;
+        assert_eq!(exp(1), 10);
+        assert_eq!(exp(3), 20);
+        assert_eq!(exp(80), 55);
+    }
+
+    #[test]
+    fn test_insert_count_pays_flat_bonus() {
+        assert_eq!(
+            skill_totals(&Token::InsertAdvanced(1)),
+            vec![("TextManipulation".to_string(), 5)]
+        );
+        assert_eq!(
+            skill_totals(&Token::InsertAdvanced(999)),
+            vec![("TextManipulation".to_string(), 10)]
+        );
+    }
+
+    #[test]
+    fn test_macro_playback_count_pays_flat_bonus() {
+        assert_eq!(
+            skill_totals(&Token::MacroPlayback(1)),
+            vec![("Macros".to_string(), 10)]
+        );
+        assert_eq!(
+            skill_totals(&Token::MacroPlayback(999)),
+            vec![("Macros".to_string(), 15)]
+        );
+    }
+
+    #[test]
+    fn test_increment_pays_finesse_bonus() {
+        assert_eq!(
+            skill_totals(&Token::Increment(1)),
+            vec![
+                ("TextManipulation".to_string(), 10),
+                ("Finesse".to_string(), 5)
+            ]
+        );
+        assert_eq!(
+            skill_totals(&Token::Increment(5)),
+            vec![
+                ("TextManipulation".to_string(), 10),
+                ("Finesse".to_string(), 10)
+            ]
+        );
     }
 }
@@ -180,6 +180,10 @@ mod tests {
             make_skill("Search", level),
             make_skill("Knowledge", level),
             make_skill("Saving", level),
+            make_skill("Macros", level),
+            make_skill("BufferManagement", level),
+            make_skill("Debugging", level),
+            make_skill("Diffing", level),
         ]
     }
 
@@ -11,6 +11,10 @@ pub enum Skills {
     Search(i32),
     Knowledge(i32),
     Saving(i32),
+    Macros(i32),
+    BufferManagement(i32),
+    Debugging(i32),
+    Diffing(i32),
 }
 
 impl Skills {
@@ -27,6 +31,10 @@ impl Skills {
             Skills::to_str(&Skills::Search(0)),
             Skills::to_str(&Skills::Knowledge(0)),
             Skills::to_str(&Skills::Saving(0)),
+            Skills::to_str(&Skills::Macros(0)),
+            Skills::to_str(&Skills::BufferManagement(0)),
+            Skills::to_str(&Skills::Debugging(0)),
+            Skills::to_str(&Skills::Diffing(0)),
         ]
     }
 
@@ -43,6 +51,10 @@ impl Skills {
             Skills::Search(_) => "Search".to_string(),
             Skills::Knowledge(_) => "Knowledge".to_string(),
             Skills::Saving(_) => "Saving".to_string(),
+            Skills::Macros(_) => "Macros".to_string(),
+            Skills::BufferManagement(_) => "BufferManagement".to_string(),
+            Skills::Debugging(_) => "Debugging".to_string(),
+            Skills::Diffing(_) => "Diffing".to_string(),
         }
     }
 
@@ -58,7 +70,11 @@ impl Skills {
             | Skills::Finesse(exp)
             | Skills::Search(exp)
             | Skills::Knowledge(exp)
-            | Skills::Saving(exp) => *exp,
+            | Skills::Saving(exp)
+            | Skills::Macros(exp)
+            | Skills::BufferManagement(exp)
+            | Skills::Debugging(exp)
+            | Skills::Diffing(exp) => *exp,
         }
     }
 }
@@ -1,3 +1,256 @@
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AntiPattern {
+    // |up|, |down|, |left|, |right|
+    ArrowKey,
+
+    // |pageup|, |pagedown|
+    PageKey,
+
+    // |home|, |end|
+    HomeEndKey,
+
+    // |mouse| (mouse clicks)
+    Mouse,
+
+    // hjkl pressed more times in a row than the hardtime limit allows
+    RepeatedMotion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExCommand {
+    // :s, :%s, :'<,'>s
+    Substitute,
+
+    // :g, :g!, :v
+    Global,
+
+    // :norm, :normal!
+    Normal,
+
+    // :sor, :sort
+    Sort,
+
+    // :e, :edit
+    Edit,
+
+    // :q, :qa
+    Quit,
+
+    // :wq, :x, :xa
+    WriteQuit,
+
+    // :sp, :vs, :new, :vnew
+    Split,
+
+    // :b, :bn, :bp, :bd, :ls
+    Buffer,
+
+    // :cn, :cp, :copen, :ccl
+    Quickfix,
+
+    // :d, :1,10d
+    Delete,
+
+    // :y, :'<,'>y
+    Yank,
+
+    // :m, :m0
+    Move,
+
+    // :co, :t
+    Copy,
+
+    // :j, :.,+3j
+    Join,
+
+    // :mak, :make
+    Make,
+
+    // :tabnew, :tabe, :tabn, :tabp, :tabc, :tabo
+    Tab,
+
+    // :args, :argadd, :n, :N, :prev, :fir, :la
+    Args,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExAddressKind {
+    // 10
+    Line(u32),
+
+    // .
+    Current,
+
+    // $
+    Last,
+
+    // 'a, '<
+    Mark(char),
+
+    // /pattern/, ?pattern?
+    Pattern(String),
+}
+
+// An Ex line address plus its +/- offset (.+3, /fn/-1, $-5)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExAddress {
+    pub kind: ExAddressKind,
+    pub offset: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExRange {
+    // %
+    WholeFile,
+
+    // 10, ., 'a
+    Single(ExAddress),
+
+    // 1,10, .,$, '<,'>
+    Span(ExAddress, ExAddress),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Delete,     // d
+    Yank,       // y
+    Change,     // c
+    Fold,       // zf
+    Indent,     // >
+    Unindent,   // <
+    Reindent,   // =
+    Format,     // gq
+    FormatKeep, // gw
+    Filter,     // !
+    Rot13,      // g?
+    ToggleCase, // g~
+    Lowercase,  // gu
+    Uppercase,  // gU
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    // dd, >>, gqq, g??, d3d
+    Line,
+
+    // w, $, j, G, }, ...
+    Key(char),
+
+    // gg, gj, g_, ge, ...
+    G(char),
+
+    // f, F, t, T + the target char
+    Find(char, char),
+
+    // / or ? + a pattern run with |enter|
+    Search(char),
+}
+
+// iw, a), i", ap, ...
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextObject {
+    pub around: bool,
+    pub object: char,
+}
+
+// An operator with what it acts on: 5dd, "ay3w, ciw, gUiw, >ip, zfj.
+// `count` is the operator count times the motion count (2d3w is 6)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OperatorAction {
+    pub operator: Operator,
+    pub motion: Option<Motion>,
+    pub text_object: Option<TextObject>,
+    pub count: i32,
+    pub register: Option<char>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BracketKind {
+    // [[, ]], [], ][
+    Section,
+
+    // [m, ]m, [M, ]M
+    Method,
+
+    // [{, ]}, [(, ])
+    Block,
+
+    // [q, ]q, [Q, ]Q, [l, ]l
+    Quickfix,
+
+    // [d, ]d, [D, ]D
+    Diagnostic,
+
+    // [c, ]c
+    Hunk,
+
+    // [b, ]b, [B, ]B
+    Buffer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowCommand {
+    // <C-W>(hjklwWptb), <C-H>, <C-J>, <C-K>, <C-L>
+    Focus,
+
+    // <C-W>s, <C-W>S, <C-W>n
+    Split,
+
+    // <C-W>v
+    VSplit,
+
+    // <C-W>q, <C-W>c
+    Close,
+
+    // <C-W>o
+    Only,
+
+    // [num]<C-W>(+-<>_|), <C-W>[num](+-<>_|)
+    Resize(i32),
+
+    // <C-W>=
+    Equalize,
+
+    // <C-W>r, <C-W>R, <C-W>x
+    Rotate,
+
+    // <C-W>H, <C-W>J, <C-W>K, <C-W>L, <C-W>T
+    Move,
+}
+
+impl OperatorAction {
+    /// The flat token this action was lexed as before operators were structured
+    pub fn legacy_token(&self) -> Token {
+        match self.operator {
+            Operator::Delete => Token::DeleteText(self.count, self.register),
+            Operator::Yank => Token::YankPaste(self.register),
+            Operator::Change
+            | Operator::Rot13
+            | Operator::ToggleCase
+            | Operator::Lowercase
+            | Operator::Uppercase => Token::TextManipulationAdvanced,
+            Operator::Fold => Token::FoldCreate,
+            Operator::Indent | Operator::Unindent => Token::IndentText(self.count),
+            Operator::Reindent | Operator::Format | Operator::FormatKeep => Token::FormatText,
+            Operator::Filter => Token::FilterText,
+        }
+    }
+}
+
+impl AntiPattern {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            AntiPattern::ArrowKey => "ArrowKey",
+            AntiPattern::PageKey => "PageKey",
+            AntiPattern::HomeEndKey => "HomeEndKey",
+            AntiPattern::Mouse => "Mouse",
+            AntiPattern::RepeatedMotion => "RepeatedMotion",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // gj, gk, j, k, 10j,
@@ -8,15 +261,18 @@ pub enum Token {
     // 10h, h, l
     MoveHorizontalBasic(i32),
 
-    // 10<C-U>, <C-U>, <C-D>
+    // 10<C-U>, <C-U>, <C-D>, {, }, 3}
     MoveVerticalChunk(i32),
 
-    // 10w, w, W, e, E, b, B
+    // 10w, w, W, e, E, b, B, (, ), 3)
     MoveHorizontalChunk(i32),
 
     // f, F, t, T + any 1 char
     JumpToHorizontal,
 
+    // 0, ^, $, 3$, g_, g0, g$, gm, |, 5|
+    JumpToLinePosition(i32),
+
     // :10|enter|, 10gg, gg, G
     JumpToLineNumber(String),
 
@@ -26,21 +282,57 @@ pub enum Token {
     // %
     JumpFromContext,
 
+    // <C-O>, <C-I>, |tab|, g;, g,, '', ``
+    JumpList,
+
     // zz, zb, zt, <C-E>, <C-Y>
     CameraMovement,
 
-    // <C-W>(svwqx=hljkHLJK), <C-H>, <C-J>, <C-K>, <C-L>
-    WindowManagement,
+    // zf{motion}, zF, visual zf
+    FoldCreate,
+
+    // zd, zD, zE
+    FoldDelete,
+
+    // zo, zc, za, zR, zM and the other open/close commands
+    FoldToggle,
 
-    // [num](x, X, gJ, J, r[character])
+    // zj, zk, 3zj
+    FoldMove(i32),
+
+    // <C-W> + sub-command, <C-H>, <C-J>, <C-K>, <C-L>
+    WindowManagement(WindowCommand),
+
+    // [num](gJ, J, r[character])
     TextManipulationBasic(i32),
 
     // R[character]|escape|, g(~uU)[num](wWeEbB$^0fFtT),
-    // cc, cw, c$, ciw, caw, ci), ca}, s, S, C, ~
+    // cc, cw, c$, ciw, caw, ci), ca}, s, S, C, ~, g?w, g??
     TextManipulationAdvanced,
 
-    // p, P, Y, yy, yw, y$, yiw, yaw
-    YankPaste,
+    // [num]>>, <<, >j, <ip, >}
+    IndentText(i32),
+
+    // [num]<C-A>, [num]<C-X>, visual <C-A>, visual <C-X>
+    Increment(i32),
+
+    // visual g<C-A>, visual g<C-X>
+    IncrementSequence,
+
+    // [num](i, a, gI)
+    InsertBasic(i32),
+
+    // [num](I, A, o, O, gi)
+    InsertAdvanced(i32),
+
+    // ==, =ip, gg=G, gqq, gqap, gwip
+    FormatText,
+
+    // !!sort|enter|, !ipsort|enter|
+    FilterText,
+
+    // p, P, Y, yy, yw, y$, yiw, yaw, with an optional "{register} prefix
+    YankPaste(Option<char>),
 
     // u, U, <C-R>
     UndoRedo,
@@ -54,11 +346,24 @@ pub enum Token {
     // n, N (repeat last search), ;, , (repeat last f/F/t/T)
     SearchRepeat,
 
+    // [num](*, #, g*, g#)
+    SearchWord(i32),
+
+    // gd, gD
+    GotoDefinition,
+
+    // gt, gT, 3gt, <C-^>, <C-6>
+    BufferSwitch,
+
+    // [num]([ or ] + [ ] m M { } ( ) q Q l L d D c b B), see `BracketKind`
+    BracketJump(BracketKind, i32),
+
     // m{char} (set mark), '{char} (jump to mark line), `{char} (jump to mark position)
     Marks,
 
-    // dd, dw, d$, dW, de, dE, db, dB, d^, d0, D, diw, daw, di), da}
-    DeleteText(i32),
+    // dd, dw, d$, dW, de, dE, db, dB, d^, d0, D, x, X, diw, daw, di), da},
+    // with an optional "{register} prefix
+    DeleteText(i32, Option<char>),
 
     // :[any characters] followed by |escape| or |enter|
     Command(bool),
@@ -68,4 +373,48 @@ pub enum Token {
 
     // :w followed by |enter| or |escape|
     SaveFile(bool),
+
+    // :%s/a/b/|enter|, :g/re/d|enter|, :1,10d|enter|, :sp|enter|
+    ExCommand(ExCommand, Option<ExRange>, bool),
+
+    // q{register}
+    MacroRecordStart,
+
+    // q (while recording)
+    MacroRecordStop,
+
+    // @{register}, @@, 10@a
+    MacroPlayback(i32),
+
+    // v, V, <C-V> + [motions/text objects] + (d, x, c, s, D, X, C, S, R, r, ~, u, U,
+    // J, gu, gU, g~, gJ, >, <, I, A), with the register the deletes went through ("a)
+    VisualManipulation(i32, Option<char>),
+
+    // v, V, <C-V> + [motions/text objects] + ["{register}] + (y, Y, p, P)
+    VisualYank(i32, Option<char>),
+
+    // v, V, <C-V> + [motions/text objects] followed by |escape|, |enter|, <C-C> or :
+    VisualSelection(i32),
+
+    // d, y, c, >, <, =, !, gq, gw, g?, g~, gu, gU, zf + motion or text object,
+    // and the x, X, s, S, C, D, Y shorthands, see `OperatorAction`.
+    // `legacy_token` maps it onto the flat variants above
+    Operator(OperatorAction),
+
+    // Habits tracked by hardtime mode, see `AntiPattern`
+    AntiPattern(AntiPattern),
+}
+
+/// A token plus the byte range of the lexer input it was lexed from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Range<usize>,
+}
+
+impl SpannedToken {
+    /// The raw keys behind the token, `input` being what the lexer was given
+    pub fn raw<'a>(&self, input: &'a str) -> &'a str {
+        input.get(self.span.clone()).unwrap_or_default()
+    }
 }
@@ -3,7 +3,7 @@ use std::io::Write;
 use std::path::PathBuf;
 use std::sync::{LazyLock, Mutex};
 
-use crate::token::Token;
+use crate::token::SpannedToken;
 
 struct TokenLogConfig {
     enabled: bool,
@@ -26,7 +26,10 @@ pub fn enable(db_path: &str) {
 
     if let Ok(mut file) = File::create(&path) {
         let _ = writeln!(file, "# Vimscape2007 Token Log");
-        let _ = writeln!(file, "# Format: Token: <Debug representation>");
+        let _ = writeln!(
+            file,
+            "# Format: Token: <Debug representation>\tKeys[<start>..<end>]: <raw keys>"
+        );
         let _ = writeln!(file, "# ========================================");
     }
 
@@ -37,10 +40,7 @@ pub fn enable(db_path: &str) {
 }
 
 pub fn is_enabled() -> bool {
-    TOKEN_LOG_CONFIG
-        .lock()
-        .map(|config| config.enabled)
-        .unwrap_or(false)
+    TOKEN_LOG_CONFIG.lock().is_ok_and(|config| config.enabled)
 }
 
 fn open_log_file() -> Option<File> {
@@ -51,9 +51,18 @@ fn open_log_file() -> Option<File> {
     OpenOptions::new().append(true).open(&config.log_path).ok()
 }
 
-pub fn log_token(token: &Token) {
+/// Log a token next to the raw keys it came from. `input` is the string the
+/// lexer was given, the span indexes into it.
+pub fn log_token(spanned: &SpannedToken, input: &str) {
     if let Some(mut file) = open_log_file() {
-        let _ = writeln!(file, "Token: {token:?}");
+        let _ = writeln!(
+            file,
+            "Token: {:?}\tKeys[{}..{}]: {}",
+            spanned.token,
+            spanned.span.start,
+            spanned.span.end,
+            spanned.raw(input)
+        );
     }
 }
 